@@ -86,8 +86,8 @@ where
                 RecvMsg::RpcResponse(_type, body) => {
                     return f(&body).map_err(|err| err.into());
                 }
-                RecvMsg::ErrorResponse(message) => {
-                    return std::result::Result::Err(Box::new(AppError::new(message)));
+                RecvMsg::ErrorResponse(err) => {
+                    return std::result::Result::Err(Box::new(AppError::new(err.to_string())));
                 }
                 _ => {}
             }
@@ -113,8 +113,8 @@ where
                     let display = f(&body)?;
                     println!("{:?}", display);
                 }
-                RecvMsg::ErrorResponse(message) => {
-                    return std::result::Result::Err(Box::new(AppError::new(message)));
+                RecvMsg::ErrorResponse(err) => {
+                    return std::result::Result::Err(Box::new(AppError::new(err.to_string())));
                 }
                 RecvMsg::CancelStreamResponse() => break,
                 _ => {}
@@ -183,7 +183,8 @@ async fn main() -> Result<()> {
         BoxStream::from_handshake(&socket, &socket, handshake, 0x8000).split_read_write();
 
     let mut rpc_reader = RpcReader::new(box_stream_read);
-    let mut client = ApiCaller::new(RpcWriter::new(box_stream_write));
+    let mut client =
+        ApiCaller::new(RpcWriter::new(box_stream_write).peer_streams(rpc_reader.peer_streams()));
 
     let req_id = client.whoami_req_send().await?;
     let whoami = match get_async(&mut rpc_reader, req_id, whoami_res_parse).await {
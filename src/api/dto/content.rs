@@ -1,25 +1,75 @@
 #![allow(clippy::large_enum_variant)]
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{Map, Value};
+
+use crate::feed::Message;
 
 pub type SsbHash = String;
 pub type SsbId = String;
 pub type SsbMsgType = String;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Mention {
     pub link: SsbId,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Other fields of the mention, like the `type` and `size` of blobs.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Mention {
+    pub fn new(link: SsbId, name: Option<String>) -> Self {
+        Mention {
+            link,
+            name,
+            extra: Map::new(),
+        }
+    }
+}
+
+/// A link found in a list, either as a plain id or as a mention object.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LinkRef {
+    Id(SsbId),
+    Mention(Mention),
+}
+
+impl LinkRef {
+    pub fn link(&self) -> &str {
+        match self {
+            LinkRef::Id(link) => link,
+            LinkRef::Mention(mention) => &mention.link,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Post {
     #[serde(rename = "type")]
     pub xtype: String,
     pub text: String,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub mentions: Option<Vec<Mention>>,
+    pub root: Option<SsbHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<Branch>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork: Option<SsbHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recps: Option<Vec<LinkRef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mentions: Option<Mentions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "contentWarning")]
+    pub content_warning: Option<String>,
+    /// Other fields of the post, like the `tangles` or client specific ones.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Post {
@@ -27,15 +77,101 @@ impl Post {
         Post {
             xtype: String::from("post"),
             text,
-            mentions,
+            root: None,
+            branch: None,
+            fork: None,
+            channel: None,
+            recps: None,
+            mentions: mentions.map(|mentions| {
+                Mentions::Vector(mentions.into_iter().map(LinkRef::Mention).collect())
+            }),
+            content_warning: None,
+            extra: Map::new(),
+        }
+    }
+    pub fn root(self, root: SsbHash) -> Self {
+        Self {
+            root: Some(root),
+            ..self
         }
     }
+    pub fn branch(self, branch: Branch) -> Self {
+        Self {
+            branch: Some(branch),
+            ..self
+        }
+    }
+    pub fn fork(self, fork: SsbHash) -> Self {
+        Self {
+            fork: Some(fork),
+            ..self
+        }
+    }
+    pub fn channel(self, channel: String) -> Self {
+        Self {
+            channel: Some(channel),
+            ..self
+        }
+    }
+    pub fn recps(self, recps: Vec<LinkRef>) -> Self {
+        Self {
+            recps: Some(recps),
+            ..self
+        }
+    }
+    /// Make the post a reply in the thread started by `root`. `replies` are
+    /// the messages already in the thread, the `branch` points to the ones
+    /// that have not been replied yet.
+    pub fn reply(self, root: &Message, replies: &[Message]) -> Self {
+        let root_id = root.id().to_string();
+
+        let mut referenced = HashSet::new();
+        for reply in replies {
+            if let Some(branch) = reply.content().get("branch") {
+                if let Ok(branch) = serde_json::from_value::<Branch>(branch.clone()) {
+                    referenced.extend(branch.links().into_iter().map(String::from));
+                }
+            }
+        }
+
+        let mut heads: Vec<_> = std::iter::once(root)
+            .chain(replies.iter())
+            .map(|msg| msg.id().to_string())
+            .filter(|id| !referenced.contains(id))
+            .collect();
+        heads.sort();
+        heads.dedup();
+
+        let branch = match heads.len() {
+            0 => Branch::One(root_id.clone()),
+            1 => Branch::One(heads.remove(0)),
+            _ => Branch::Many(heads),
+        };
+
+        self.root(root_id).branch(branch)
+    }
     pub fn to_msg(&self) -> serde_json::Result<serde_json::Value> {
         serde_json::to_value(self)
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<Post> for TypedMessage {
+    fn from(post: Post) -> Self {
+        TypedMessage::Post {
+            text: post.text,
+            root: post.root,
+            branch: post.branch,
+            fork: post.fork,
+            channel: post.channel,
+            recps: post.recps,
+            mentions: post.mentions,
+            content_warning: post.content_warning,
+            extra: post.extra,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PubAddress {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub host: Option<String>,
@@ -43,22 +179,54 @@ pub struct PubAddress {
     pub key: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum VoteValue {
     Numeric(i64),
     Boolean(bool),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl VoteValue {
+    /// If the vote is a like, unlikes are `0`, negative or `false`.
+    pub fn is_like(&self) -> bool {
+        match self {
+            VoteValue::Numeric(value) => *value > 0,
+            VoteValue::Boolean(value) => *value,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Vote {
-    link: SsbHash,
-    value: VoteValue,
+    pub link: SsbHash,
+    pub value: VoteValue,
     #[serde(skip_serializing_if = "Option::is_none")]
-    expression: Option<String>,
+    pub expression: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Vote {
+    pub fn new(link: SsbHash, value: VoteValue) -> Self {
+        Vote {
+            link,
+            value,
+            expression: None,
+        }
+    }
+    pub fn expression(self, expression: String) -> Self {
+        Self {
+            expression: Some(expression),
+            ..self
+        }
+    }
+}
+
+impl From<Vote> for TypedMessage {
+    fn from(vote: Vote) -> Self {
+        TypedMessage::Vote { vote }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Image {
     OnlyLink(SsbHash),
@@ -76,29 +244,255 @@ pub enum Image {
     },
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Image {
+    pub fn link(&self) -> &SsbHash {
+        match self {
+            Image::OnlyLink(link) => link,
+            Image::Complete { link, .. } => link,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DateTime {
-    epoch: u64,
-    tz: String,
+    pub epoch: u64,
+    pub tz: String,
+}
+
+impl DateTime {
+    pub fn new(epoch: u64, tz: String) -> Self {
+        DateTime { epoch, tz }
+    }
+}
+
+/// A feed joining or leaving a gathering.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attendee {
+    pub link: SsbId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove: Option<bool>,
+}
+
+impl Attendee {
+    pub fn new(link: SsbId, attending: bool) -> Self {
+        Attendee {
+            link,
+            remove: if attending { None } else { Some(true) },
+        }
+    }
+    pub fn is_attending(&self) -> bool {
+        !self.remove.unwrap_or(false)
+    }
+}
+
+/// An event, its details and attendees are set with `About` messages.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gathering {
+    #[serde(rename = "type")]
+    pub xtype: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progenitor: Option<SsbHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mentions: Option<Mentions>,
+}
+
+impl Default for Gathering {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gathering {
+    pub fn new() -> Self {
+        Gathering {
+            xtype: String::from("gathering"),
+            progenitor: None,
+            mentions: None,
+        }
+    }
+    pub fn progenitor(self, progenitor: SsbHash) -> Self {
+        Self {
+            progenitor: Some(progenitor),
+            ..self
+        }
+    }
+    pub fn mentions(self, mentions: Mentions) -> Self {
+        Self {
+            mentions: Some(mentions),
+            ..self
+        }
+    }
+    pub fn to_msg(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self)
+    }
+}
+
+/// Information about a feed, a message or a gathering.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct About {
+    #[serde(rename = "type")]
+    pub xtype: String,
+    pub about: SsbId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<SsbHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<Image>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "startDateTime")]
+    pub start_datetime: Option<DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attendee: Option<Attendee>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "publicWebHosting")]
+    pub public_web_hosting: Option<bool>,
+}
+
+impl About {
+    pub fn new(about: SsbId) -> Self {
+        About {
+            xtype: String::from("about"),
+            about,
+            name: None,
+            title: None,
+            branch: None,
+            image: None,
+            description: None,
+            location: None,
+            start_datetime: None,
+            attendee: None,
+            public_web_hosting: None,
+        }
+    }
+    pub fn name(self, name: String) -> Self {
+        Self {
+            name: Some(name),
+            ..self
+        }
+    }
+    pub fn title(self, title: String) -> Self {
+        Self {
+            title: Some(title),
+            ..self
+        }
+    }
+    pub fn branch(self, branch: SsbHash) -> Self {
+        Self {
+            branch: Some(branch),
+            ..self
+        }
+    }
+    pub fn image(self, image: Image) -> Self {
+        Self {
+            image: Some(image),
+            ..self
+        }
+    }
+    pub fn description(self, description: String) -> Self {
+        Self {
+            description: Some(description),
+            ..self
+        }
+    }
+    pub fn location(self, location: String) -> Self {
+        Self {
+            location: Some(location),
+            ..self
+        }
+    }
+    pub fn start_datetime(self, start_datetime: DateTime) -> Self {
+        Self {
+            start_datetime: Some(start_datetime),
+            ..self
+        }
+    }
+    pub fn attendee(self, attendee: Attendee) -> Self {
+        Self {
+            attendee: Some(attendee),
+            ..self
+        }
+    }
+    pub fn public_web_hosting(self, public_web_hosting: bool) -> Self {
+        Self {
+            public_web_hosting: Some(public_web_hosting),
+            ..self
+        }
+    }
+    pub fn to_msg(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self)
+    }
+}
+
+impl From<About> for TypedMessage {
+    fn from(about: About) -> Self {
+        TypedMessage::About {
+            about: about.about,
+            name: about.name,
+            title: about.title,
+            branch: about.branch,
+            image: about.image,
+            description: about.description,
+            location: about.location,
+            start_datetime: about.start_datetime,
+            attendee: about.attendee,
+            public_web_hosting: about.public_web_hosting,
+        }
+    }
+}
+
+impl From<Gathering> for TypedMessage {
+    fn from(gathering: Gathering) -> Self {
+        TypedMessage::Gathering {
+            progenitor: gathering.progenitor,
+            mentions: gathering.mentions,
+        }
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Branch {
     One(SsbHash),
     Many(Vec<SsbHash>),
 }
 
-#[derive(Debug, Deserialize)]
+impl Branch {
+    pub fn links(&self) -> Vec<&str> {
+        match self {
+            Branch::One(link) => vec![link.as_str()],
+            Branch::Many(links) => links.iter().map(|link| link.as_str()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Mentions {
     Link(SsbHash),
     One(Mention),
-    Vector(Vec<Mention>),
+    Vector(Vec<LinkRef>),
     Map(HashMap<String, Mention>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Mentions {
+    pub fn links(&self) -> Vec<&str> {
+        match self {
+            Mentions::Link(link) => vec![link.as_str()],
+            Mentions::One(mention) => vec![mention.link.as_str()],
+            Mentions::Vector(links) => links.iter().map(|link| link.link()).collect(),
+            Mentions::Map(mentions) => mentions.values().map(|m| m.link.as_str()).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum TypedMessage {
     #[serde(rename = "pub")]
@@ -107,7 +501,22 @@ pub enum TypedMessage {
     Post {
         text: String,
         #[serde(skip_serializing_if = "Option::is_none")]
-        mentions: Option<Vec<Mention>>,
+        root: Option<SsbHash>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        branch: Option<Branch>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fork: Option<SsbHash>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recps: Option<Vec<LinkRef>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mentions: Option<Mentions>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "contentWarning")]
+        content_warning: Option<String>,
+        #[serde(flatten)]
+        extra: Map<String, Value>,
     },
     #[serde(rename = "contact")]
     Contact {
@@ -137,11 +546,38 @@ pub enum TypedMessage {
         #[serde(skip_serializing_if = "Option::is_none")]
         #[serde(rename = "startDateTime")]
         start_datetime: Option<DateTime>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        attendee: Option<Attendee>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(rename = "publicWebHosting")]
+        public_web_hosting: Option<bool>,
     },
     #[serde(rename = "channel")]
     Channel { channel: String, subscribed: bool },
     #[serde(rename = "vote")]
     Vote { vote: Vote },
+    #[serde(rename = "gathering")]
+    Gathering {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        progenitor: Option<SsbHash>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mentions: Option<Mentions>,
+    },
+}
+
+impl TypedMessage {
+    /// The `type` field of the message.
+    pub fn xtype(&self) -> &'static str {
+        match self {
+            TypedMessage::Pub { .. } => "pub",
+            TypedMessage::Post { .. } => "post",
+            TypedMessage::Contact { .. } => "contact",
+            TypedMessage::About { .. } => "about",
+            TypedMessage::Channel { .. } => "channel",
+            TypedMessage::Vote { .. } => "vote",
+            TypedMessage::Gathering { .. } => "gathering",
+        }
+    }
 }
 
 /// An ssb-ql-1 query as defined by the 'Subset replication for SSB'
@@ -195,3 +631,72 @@ pub struct FriendsHops {
 pub struct InviteCreateOptions {
     pub uses: u16,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keystore::OwnedIdentity;
+
+    #[test]
+    fn test_post_roundtrip() -> serde_json::Result<()> {
+        let posts = [
+            r##"{"type":"post","text":"hi @alice and #kuska","root":"%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256","branch":["%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256","%seUEAo7PTyA7vNwnOrmGIsUFfpyRzOvzGVv1QCb/Fz8=.sha256"],"channel":"kuska","recps":["@BIbVppzlrNiRJogxDYz3glUS7G4s4D4NiXiPEAEzxdE=.ed25519",{"link":"@hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519","name":"paul"}],"mentions":["%7UKRfZb2u8al4tYWHqM55R9xpE/KKVh9U0M6BdugGt4=.sha256",{"link":"@hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519","name":"alice"},{"link":"#kuska"},{"link":"&uaGieSQDJcHfUp6hjIcIq55GoZh4Ug7tNmgaohoxrpw=.sha256","name":"image.png","type":"image/png","size":1234,"width":64,"height":64}]}"##,
+            r##"{"type":"post","text":"fork","root":"%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256","branch":"%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256","fork":"%seUEAo7PTyA7vNwnOrmGIsUFfpyRzOvzGVv1QCb/Fz8=.sha256","mentions":{"link":"@hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519"},"contentWarning":"spoilers"}"##,
+            r##"{"type":"post","text":"in the wild","root":"%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256","branch":"%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256","contentWarning":"spoilers","reply":{"%Cg0ZpZ8cV85G8UIIropgBOvM8+Srlv9LSGDNGnpdK44=.sha256":"@hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519"},"tangles":{"comment":{"root":null,"previous":null}},"application":"manyverse"}"##,
+        ];
+        for post in posts.iter() {
+            let typed: TypedMessage = serde_json::from_str(post)?;
+            assert_eq!(&serde_json::to_string(&typed)?, post);
+            let untyped: Post = serde_json::from_str(post)?;
+            assert_eq!(&serde_json::to_string(&untyped)?, post);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_extra_fields_any_order() -> serde_json::Result<()> {
+        let post = r##"{"application":"patchwork","text":"unknown keys first","tangles":{"comment":{"root":null,"previous":null}},"type":"post","channel":"kuska","reply":{},"contentWarning":"cw","x-custom":[1,2]}"##;
+        let expected: Value = serde_json::from_str(post)?;
+
+        let typed: TypedMessage = serde_json::from_str(post)?;
+        assert_eq!(serde_json::to_value(&typed)?, expected);
+        let untyped: Post = serde_json::from_str(post)?;
+        assert_eq!(untyped.channel.as_deref(), Some("kuska"));
+        assert_eq!(untyped.content_warning.as_deref(), Some("cw"));
+        assert_eq!(
+            untyped.extra.keys().collect::<Vec<_>>(),
+            vec!["application", "tangles", "reply", "x-custom"]
+        );
+        assert_eq!(serde_json::to_value(&untyped)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_post_reply_branch() -> crate::feed::Result<()> {
+        let id = OwnedIdentity::create();
+        let root = Message::sign(None, &id, Post::new("root".to_string(), None).to_msg()?)?;
+
+        let reply = Post::new("first".to_string(), None).reply(&root, &[]);
+        assert_eq!(reply.root, Some(root.id().to_string()));
+        assert_eq!(reply.branch, Some(Branch::One(root.id().to_string())));
+
+        let first = Message::sign(Some(&root), &id, reply.to_msg()?)?;
+        let second = Message::sign(
+            Some(&first),
+            &id,
+            Post::new("second".to_string(), None)
+                .reply(&root, &[])
+                .to_msg()?,
+        )?;
+
+        let reply = Post::new("third".to_string(), None).reply(&root, std::slice::from_ref(&first));
+        assert_eq!(reply.branch, Some(Branch::One(first.id().to_string())));
+
+        let reply =
+            Post::new("third".to_string(), None).reply(&root, &[first.clone(), second.clone()]);
+        let mut heads = vec![first.id().to_string(), second.id().to_string()];
+        heads.sort();
+        assert_eq!(reply.branch, Some(Branch::Many(heads)));
+        Ok(())
+    }
+}
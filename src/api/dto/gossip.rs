@@ -0,0 +1,11 @@
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GossipPingIn {
+    // timeout : Milliseconds between pings.
+    pub timeout: u64,
+}
+
+impl GossipPingIn {
+    pub fn new(timeout: u64) -> Self {
+        Self { timeout }
+    }
+}
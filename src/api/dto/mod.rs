@@ -2,6 +2,7 @@ mod blobs;
 pub mod content;
 mod ebt;
 mod error;
+mod gossip;
 mod history_stream;
 mod latest;
 mod stream;
@@ -11,6 +12,7 @@ mod whoami;
 pub use blobs::*;
 pub use ebt::*;
 pub use error::*;
+pub use gossip::*;
 pub use history_stream::*;
 pub use latest::*;
 pub use stream::*;
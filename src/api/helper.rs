@@ -4,16 +4,20 @@ use crate::{
         TypedMessage,
     },
     feed::Message,
-    rpc::{ArgType, Body, BodyType, RequestNo, RpcType, RpcWriter},
+    index,
+    rpc::{self, ArgType, Body, BodyType, Keepalive, RecvMsg, RequestNo, RpcType, RpcWriter},
 };
 use async_std::io::Write;
+use futures::{Stream, StreamExt};
+use std::time::SystemTime;
 
-use super::{dto, error::Result};
+use super::{dto, error::Result, manifest::Manifest};
 
 const MAX_RPC_BODY_LEN: usize = 65536;
 
 #[derive(Debug)]
 pub enum ApiMethod {
+    BlobsAdd,
     BlobsCreateWants,
     BlobsGet,
     CreateFeedStream,
@@ -24,10 +28,12 @@ pub enum ApiMethod {
     FriendsIsFollowing,
     FriendsIsBlocking,
     Get,
+    GossipPing,
     GetSubset,
     InviteCreate,
     InviteUse,
     Latest,
+    Manifest,
     NamesGet,
     NamesGetImageFor,
     NamesGetSignifier,
@@ -41,6 +47,7 @@ impl ApiMethod {
     pub fn selector(&self) -> &'static [&'static str] {
         use ApiMethod::*;
         match self {
+            BlobsAdd => &["blobs", "add"],
             BlobsCreateWants => &["blobs", "createWants"],
             BlobsGet => &["blobs", "get"],
             CreateFeedStream => &["createFeedStream"],
@@ -51,10 +58,12 @@ impl ApiMethod {
             FriendsIsBlocking => &["friends", "isBlocking"],
             FriendsIsFollowing => &["friends", "isFollowing"],
             Get => &["get"],
+            GossipPing => &["gossip", "ping"],
             GetSubset => &["partialReplication", "getSubset"],
             InviteCreate => &["invite", "create"],
             InviteUse => &["invite", "use"],
             Latest => &["latest"],
+            Manifest => &["manifest"],
             NamesGet => &["names", "get"],
             NamesGetImageFor => &["names", "getImageFor"],
             NamesGetSignifier => &["names", "getSignifier"],
@@ -67,6 +76,7 @@ impl ApiMethod {
     pub fn from_selector(s: &[&str]) -> Option<Self> {
         use ApiMethod::*;
         match s {
+            ["blobs", "add"] => Some(BlobsAdd),
             ["blobs", "createWants"] => Some(BlobsCreateWants),
             ["blobs", "get"] => Some(BlobsGet),
             ["createFeedStream"] => Some(CreateFeedStream),
@@ -77,10 +87,12 @@ impl ApiMethod {
             ["friends", "isBlocking"] => Some(FriendsIsBlocking),
             ["friends", "isFollowing"] => Some(FriendsIsFollowing),
             ["get"] => Some(Get),
+            ["gossip", "ping"] => Some(GossipPing),
             ["partialReplication", "getSubset"] => Some(GetSubset),
             ["invite", "create"] => Some(InviteCreate),
             ["invite", "use"] => Some(InviteUse),
             ["latest"] => Some(Latest),
+            ["manifest"] => Some(Manifest),
             ["names", "get"] => Some(NamesGet),
             ["names", "getImageFor"] => Some(NamesGetImageFor),
             ["names", "getSignifier"] => Some(NamesGetSignifier),
@@ -97,6 +109,13 @@ impl ApiMethod {
     }
 }
 
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
 pub struct ApiCaller<W: Write + Unpin> {
     rpc: RpcWriter<W>,
 }
@@ -110,6 +129,23 @@ impl<W: Write + Unpin> ApiCaller<W> {
         &mut self.rpc
     }
 
+    /// Send ["blobs","add"] sink request, optionally checking the blob
+    /// against `hash`. The blob is sent with `rpc().request_sink(req_no)`.
+    pub async fn blobs_add_req_send(&mut self, hash: Option<&str>) -> Result<RequestNo> {
+        let args: Vec<&str> = hash.into_iter().collect();
+        let req_no = self
+            .rpc
+            .send_request(
+                ApiMethod::BlobsAdd.selector(),
+                RpcType::Sink,
+                ArgType::Object,
+                &args,
+                &None::<()>,
+            )
+            .await?;
+        Ok(req_no)
+    }
+
     /// Send blob create wants.
     pub async fn blob_create_wants_req_send(&mut self) -> Result<RequestNo> {
         let args: [&str; 0] = [];
@@ -354,6 +390,136 @@ impl<W: Write + Unpin> ApiCaller<W> {
         Ok(req_no)
     }
 
+    /// Answer a ["partialReplication", "getSubset"] request with the data
+    /// events of the `messages` matching the query in `args`, or with an
+    /// error if the query is not valid.
+    pub async fn getsubset_res_send<'a, I>(
+        &mut self,
+        req_no: RequestNo,
+        args: &serde_json::Value,
+        messages: I,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a Message>,
+    {
+        let subset = serde_json::from_value::<SubsetQuery>(args[0].clone())
+            .map_err(index::Error::from)
+            .and_then(|query| {
+                let opts = serde_json::from_value::<Option<SubsetQueryOptions>>(args[1].clone())?;
+                index::get_subset(messages, &query, opts.as_ref())
+            });
+
+        match subset {
+            Ok(events) => {
+                for event in events {
+                    self.rpc
+                        .send_response(
+                            req_no,
+                            RpcType::Source,
+                            BodyType::JSON,
+                            event.to_string().as_bytes(),
+                        )
+                        .await?;
+                }
+                self.rpc.send_stream_eof(req_no).await?;
+            }
+            Err(err) => {
+                self.rpc
+                    .send_error(req_no, RpcType::Source, &err.to_string())
+                    .await?
+            }
+        }
+        Ok(())
+    }
+
+    /// Send ["gossip", "ping"] duplex request, used as a keepalive.
+    pub async fn gossip_ping_req_send(&mut self, args: &dto::GossipPingIn) -> Result<RequestNo> {
+        let req_no = self
+            .rpc
+            .send_request(
+                ApiMethod::GossipPing.selector(),
+                RpcType::Duplex,
+                ArgType::Array,
+                &args,
+                &None::<()>,
+            )
+            .await?;
+        Ok(req_no)
+    }
+
+    /// Send a ping, the current timestamp, on our ["gossip", "ping"] request.
+    pub async fn gossip_ping_send(&mut self, req_no: RequestNo) -> Result<()> {
+        self.rpc
+            .request_sink(req_no)
+            .send_json(&now_millis())
+            .await?;
+        Ok(())
+    }
+
+    /// Answer a ping on a ["gossip", "ping"] request of the peer with the
+    /// current timestamp.
+    pub async fn gossip_ping_res_send(&mut self, req_no: RequestNo) -> Result<()> {
+        self.rpc
+            .response_sink(req_no)
+            .send_json(&now_millis())
+            .await?;
+        Ok(())
+    }
+
+    /// Receive the next message of `messages`, e.g. an
+    /// `RpcReader::into_stream`, keeping the connection alive: when the
+    /// peer is silent for `keepalive.interval()` a ping is sent on a
+    /// ["gossip", "ping"] request, opened on the first one. Any message
+    /// counts as an answer, the ones on our ping request are not returned.
+    ///
+    /// Fails with `rpc::Error::PingTimeout` when the peer missed too many
+    /// pings, and `rpc::Error::Closed` when the stream ends.
+    pub async fn recv_keepalive<S>(
+        &mut self,
+        keepalive: &mut Keepalive,
+        messages: &mut S,
+    ) -> Result<(RequestNo, RecvMsg)>
+    where
+        S: Stream<Item = (RequestNo, RecvMsg)> + Unpin,
+    {
+        loop {
+            // dropping `next` on timeout does not lose any message
+            match async_std::future::timeout(keepalive.until_ping(), messages.next()).await {
+                Ok(Some((req_no, msg))) => {
+                    keepalive.pong();
+                    let is_response = matches!(
+                        msg,
+                        RecvMsg::RpcResponse(..)
+                            | RecvMsg::ErrorResponse(_)
+                            | RecvMsg::CancelStreamResponse()
+                    );
+                    if !is_response || Some(req_no) != keepalive.req_no() {
+                        return Ok((req_no, msg));
+                    }
+                    if !matches!(msg, RecvMsg::RpcResponse(..)) {
+                        // the peer ended our ping request
+                        keepalive.set_req_no(None);
+                    }
+                }
+                Ok(None) => return Err(rpc::Error::Closed.into()),
+                Err(_) => {
+                    keepalive.ping()?;
+                    let req_no = match keepalive.req_no() {
+                        Some(req_no) => req_no,
+                        None => {
+                            let timeout = keepalive.interval().as_millis() as u64;
+                            let args = dto::GossipPingIn::new(timeout);
+                            let req_no = self.gossip_ping_req_send(&args).await?;
+                            keepalive.set_req_no(Some(req_no));
+                            req_no
+                        }
+                    };
+                    self.gossip_ping_send(req_no).await?;
+                }
+            }
+        }
+    }
+
     /// Send ["invite", "create"] request.
     pub async fn invite_create_req_send(&mut self, uses: u16) -> Result<RequestNo> {
         let args = InviteCreateOptions { uses };
@@ -401,6 +567,39 @@ impl<W: Write + Unpin> ApiCaller<W> {
         Ok(req_no)
     }
 
+    /// Send ["manifest"] request.
+    pub async fn manifest_req_send(&mut self) -> Result<RequestNo> {
+        let args: [&str; 0] = [];
+        let req_no = self
+            .rpc
+            .send_request(
+                ApiMethod::Manifest.selector(),
+                RpcType::Async,
+                ArgType::Array,
+                &args,
+                &None::<()>,
+            )
+            .await?;
+        Ok(req_no)
+    }
+
+    /// Send ["manifest"] response.
+    pub async fn manifest_res_send(
+        &mut self,
+        req_no: RequestNo,
+        manifest: &Manifest,
+    ) -> Result<()> {
+        self.rpc
+            .send_response(
+                req_no,
+                RpcType::Async,
+                BodyType::JSON,
+                serde_json::to_string(manifest)?.as_bytes(),
+            )
+            .await?;
+        Ok(())
+    }
+
     /// Send ["names", "get"] request.
     pub async fn names_get_req_send(&mut self) -> Result<RequestNo> {
         let args: [&str; 0] = [];
@@ -507,6 +706,27 @@ impl<W: Write + Unpin> ApiCaller<W> {
         Ok(req_no)
     }
 
+    /// Send ["tangles", "thread"] response, the data events of the thread
+    /// followed by the end of the stream.
+    pub async fn tangles_thread_res_send(
+        &mut self,
+        req_no: RequestNo,
+        events: &[serde_json::Value],
+    ) -> Result<()> {
+        for event in events {
+            self.rpc
+                .send_response(
+                    req_no,
+                    RpcType::Source,
+                    BodyType::JSON,
+                    event.to_string().as_bytes(),
+                )
+                .await?;
+        }
+        self.rpc.send_stream_eof(req_no).await?;
+        Ok(())
+    }
+
     /// Send ["whoami"] request.
     pub async fn whoami_req_send(&mut self) -> Result<RequestNo> {
         let args: [&str; 0] = [];
@@ -532,3 +752,62 @@ impl<W: Write + Unpin> ApiCaller<W> {
             .await?)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::MemoryTransport;
+    use async_std::task::block_on;
+    use std::time::Duration;
+
+    #[test]
+    fn test_keepalive_drops_silent_peer() -> Result<()> {
+        let ((client_reader, client_writer), (mut server_reader, mut server_writer)) =
+            MemoryTransport::new().connect();
+        let mut client = ApiCaller::new(client_writer);
+        let mut messages = Box::pin(client_reader.into_stream());
+        let mut keepalive = Keepalive::new(Duration::from_millis(20)).max_missed(2);
+
+        block_on(async {
+            // the peer answers the first ping, then stops answering
+            let answer = async {
+                let (req_no, msg) = server_reader.recv().await?;
+                assert!(
+                    matches!(msg, RecvMsg::RpcRequest(body) if body.name == ["gossip", "ping"])
+                );
+                assert!(matches!(
+                    server_reader.recv().await?,
+                    (_, RecvMsg::StreamRequestData(..))
+                ));
+                server_writer
+                    .response_sink(req_no)
+                    .send_json(&now_millis())
+                    .await?;
+                server_writer
+                    .send_request(
+                        &["whoami"],
+                        RpcType::Async,
+                        ArgType::Array,
+                        &(),
+                        &None::<()>,
+                    )
+                    .await
+            };
+            let (recv, answer) =
+                futures::join!(client.recv_keepalive(&mut keepalive, &mut messages), answer);
+            answer?;
+            assert!(matches!(
+                recv?,
+                (1, RecvMsg::RpcRequest(body)) if body.name == ["whoami"]
+            ));
+            assert_eq!(keepalive.missed(), 0);
+
+            let dropped = client.recv_keepalive(&mut keepalive, &mut messages).await;
+            assert!(matches!(
+                dropped,
+                Err(crate::api::Error::Rpc(rpc::Error::PingTimeout(2)))
+            ));
+            Ok(())
+        })
+    }
+}
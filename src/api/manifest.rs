@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+
+use crate::rpc::Body;
+
+/// The type of a method in a muxrpc manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum MethodType {
+    Async,
+    Sync,
+    Source,
+    Sink,
+    Duplex,
+    /// A type not known by this implementation, kept as found.
+    Unknown(String),
+}
+
+impl MethodType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MethodType::Async => "async",
+            MethodType::Sync => "sync",
+            MethodType::Source => "source",
+            MethodType::Sink => "sink",
+            MethodType::Duplex => "duplex",
+            MethodType::Unknown(method_type) => method_type,
+        }
+    }
+}
+
+impl From<String> for MethodType {
+    fn from(method_type: String) -> Self {
+        match method_type.as_str() {
+            "async" => MethodType::Async,
+            "sync" => MethodType::Sync,
+            "source" => MethodType::Source,
+            "sink" => MethodType::Sink,
+            "duplex" => MethodType::Duplex,
+            _ => MethodType::Unknown(method_type),
+        }
+    }
+}
+
+impl From<MethodType> for String {
+    fn from(method_type: MethodType) -> Self {
+        match method_type {
+            MethodType::Unknown(method_type) => method_type,
+            method_type => method_type.as_str().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ManifestEntry {
+    Method(MethodType),
+    Group(Manifest),
+}
+
+/// The methods supported by a muxrpc peer, as returned by `manifest`,
+/// e.g. `{"whoami":"async","blobs":{"get":"source","add":"sink"}}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Manifest(BTreeMap<String, ManifestEntry>);
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a method, replacing any method or group at `path`.
+    pub fn add(&mut self, path: &[&str], method_type: MethodType) {
+        let (name, groups) = match path.split_last() {
+            Some(split) => split,
+            None => return,
+        };
+        let mut manifest = self;
+        for group in groups {
+            let entry = manifest
+                .0
+                .entry(group.to_string())
+                .or_insert_with(|| ManifestEntry::Group(Manifest::new()));
+            if !matches!(entry, ManifestEntry::Group(_)) {
+                *entry = ManifestEntry::Group(Manifest::new());
+            }
+            manifest = match entry {
+                ManifestEntry::Group(group) => group,
+                ManifestEntry::Method(_) => return,
+            };
+        }
+        manifest
+            .0
+            .insert(name.to_string(), ManifestEntry::Method(method_type));
+    }
+
+    /// The type of the method at `path`, if supported.
+    pub fn get(&self, path: &[&str]) -> Option<MethodType> {
+        let (name, groups) = path.split_last()?;
+        let mut manifest = self;
+        for group in groups {
+            manifest = match manifest.0.get(*group)? {
+                ManifestEntry::Group(group) => group,
+                ManifestEntry::Method(_) => return None,
+            };
+        }
+        match manifest.0.get(*name)? {
+            ManifestEntry::Method(method_type) => Some(method_type.clone()),
+            ManifestEntry::Group(_) => None,
+        }
+    }
+
+    pub fn supports(&self, path: &[&str]) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// All the methods, with their full paths.
+    pub fn methods(&self) -> Vec<(Vec<String>, MethodType)> {
+        let mut methods = Vec::new();
+        self.collect_methods(&mut Vec::new(), &mut methods);
+        methods
+    }
+
+    fn collect_methods(
+        &self,
+        path: &mut Vec<String>,
+        methods: &mut Vec<(Vec<String>, MethodType)>,
+    ) {
+        for (name, entry) in &self.0 {
+            path.push(name.clone());
+            match entry {
+                ManifestEntry::Method(method_type) => {
+                    methods.push((path.clone(), method_type.clone()))
+                }
+                ManifestEntry::Group(group) => group.collect_methods(path, methods),
+            }
+            path.pop();
+        }
+    }
+}
+
+/// Handlers of the methods served by a peer, the manifest is generated from
+/// the registered ones.
+#[derive(Debug)]
+pub struct HandlerRegistry<H> {
+    handlers: Vec<(Vec<String>, MethodType, H)>,
+}
+
+impl<H> Default for HandlerRegistry<H> {
+    fn default() -> Self {
+        HandlerRegistry {
+            handlers: Vec::new(),
+        }
+    }
+}
+
+impl<H> HandlerRegistry<H> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the handler of the method at `path`, replacing any previous one.
+    pub fn register(&mut self, path: &[&str], method_type: MethodType, handler: H) {
+        self.handlers
+            .retain(|(registered, _, _)| *registered != path);
+        let path = path.iter().map(|name| name.to_string()).collect();
+        self.handlers.push((path, method_type, handler));
+    }
+
+    /// Find the handler of the method at `path`.
+    pub fn find(&self, path: &[&str]) -> Option<(MethodType, &H)> {
+        self.handlers
+            .iter()
+            .find(|(registered, _, _)| *registered == path)
+            .map(|(_, method_type, handler)| (method_type.clone(), handler))
+    }
+
+    /// Find the handler of a request.
+    pub fn find_for(&self, body: &Body) -> Option<(MethodType, &H)> {
+        let path: Vec<_> = body.name.iter().map(String::as_str).collect();
+        self.find(&path)
+    }
+
+    /// The manifest of the registered methods, `manifest` itself included.
+    pub fn manifest(&self) -> Manifest {
+        let mut manifest = Manifest::new();
+        manifest.add(&["manifest"], MethodType::Sync);
+        for (path, method_type, _) in &self.handlers {
+            let path: Vec<_> = path.iter().map(String::as_str).collect();
+            manifest.add(&path, method_type.clone());
+        }
+        manifest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MANIFEST: &str = r#"{"blobs":{"add":"sink","get":"source"},"createHistoryStream":"source","ebt":{"replicate":"duplex"},"manifest":"sync","plugin":{"weird":"stream"},"whoami":"async"}"#;
+
+    #[test]
+    fn test_parse_manifest() -> serde_json::Result<()> {
+        let manifest: Manifest = serde_json::from_str(MANIFEST)?;
+        assert_eq!(manifest.get(&["whoami"]), Some(MethodType::Async));
+        assert_eq!(manifest.get(&["blobs", "add"]), Some(MethodType::Sink));
+        assert_eq!(
+            manifest.get(&["ebt", "replicate"]),
+            Some(MethodType::Duplex)
+        );
+        assert_eq!(
+            manifest.get(&["plugin", "weird"]),
+            Some(MethodType::Unknown("stream".to_string()))
+        );
+        assert!(!manifest.supports(&["blobs"]));
+        assert!(!manifest.supports(&["blobs", "get", "more"]));
+        assert!(!manifest.supports(&["tangles", "thread"]));
+        assert_eq!(manifest.methods().len(), 7);
+        assert_eq!(serde_json::to_string(&manifest)?, MANIFEST);
+        Ok(())
+    }
+
+    #[test]
+    fn test_handler_registry_manifest() -> serde_json::Result<()> {
+        let mut handlers = HandlerRegistry::new();
+        handlers.register(&["whoami"], MethodType::Async, 1);
+        handlers.register(&["blobs", "get"], MethodType::Source, 2);
+        handlers.register(&["blobs", "add"], MethodType::Sink, 3);
+        handlers.register(&["ebt", "replicate"], MethodType::Duplex, 4);
+        handlers.register(&["createHistoryStream"], MethodType::Source, 5);
+        handlers.register(
+            &["plugin", "weird"],
+            MethodType::Unknown("stream".to_string()),
+            6,
+        );
+        handlers.register(&["blobs", "get"], MethodType::Source, 7);
+
+        assert_eq!(
+            handlers.find(&["blobs", "get"]),
+            Some((MethodType::Source, &7))
+        );
+        assert_eq!(handlers.find(&["blobs"]), None);
+
+        let manifest = handlers.manifest();
+        assert_eq!(serde_json::to_string(&manifest)?, MANIFEST);
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 pub mod dto;
 mod error;
 mod helper;
+mod manifest;
 
 pub use error::{Error, Result};
 pub use helper::{ApiCaller, ApiMethod};
+pub use manifest::{HandlerRegistry, Manifest, ManifestEntry, MethodType};
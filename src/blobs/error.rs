@@ -0,0 +1,19 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("error decoding base64")]
+    Base64Decode(#[from] base64::DecodeError),
+    #[error("invalid blob link")]
+    InvalidLink,
+    #[error("invalid blob key")]
+    InvalidKey,
+    #[error("failed to decipher")]
+    FailedToDecipher,
+    #[error("unexpected end of boxed blob")]
+    UnexpectedEnd,
+    #[error("data after end of boxed blob")]
+    TrailingData,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
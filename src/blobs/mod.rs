@@ -0,0 +1,5 @@
+mod error;
+mod unbox;
+
+pub use error::{Error, Result};
+pub use unbox::{box_blob, unbox_blob, BlobLink, BlobUnboxer, BOXS_SUFFIX};
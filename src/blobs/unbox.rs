@@ -0,0 +1,276 @@
+use std::{fmt, str::FromStr};
+
+use kuska_sodiumoxide::crypto::{hash::sha256, secretbox};
+
+use super::error::{Error, Result};
+use crate::crypto::{ToSodiumObject, ToSsbId};
+
+/// Suffix of the key in a `?unbox=` blob link.
+pub const BOXS_SUFFIX: &str = ".boxs";
+
+const UNBOX_QUERY: &str = "?unbox=";
+
+// boxed blobs use the pull-box-stream framing with a zero nonce, each frame
+// is a boxed header (body length + body mac) followed by the body.
+const MAX_CHUNK_LEN: usize = 4096;
+const HEADER_LEN: usize = 2 + secretbox::MACBYTES;
+const BOXED_HEADER_LEN: usize = HEADER_LEN + secretbox::MACBYTES;
+
+/// A link to a blob, optionally carrying the key needed to unbox it,
+/// i.e. `&<hash>.sha256?unbox=<key>.boxs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobLink {
+    pub id: String,
+    pub key: Option<secretbox::Key>,
+}
+
+impl BlobLink {
+    pub fn new(id: String, key: Option<secretbox::Key>) -> Self {
+        BlobLink { id, key }
+    }
+
+    /// The `?unbox=<key>.boxs` part of the link, if it has a key.
+    pub fn query(&self) -> Option<String> {
+        self.key
+            .as_ref()
+            .map(|key| format!("{}{}{}", UNBOX_QUERY, base64::encode(key), BOXS_SUFFIX))
+    }
+}
+
+impl fmt::Display for BlobLink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.id, self.query().unwrap_or_default())
+    }
+}
+
+impl FromStr for BlobLink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (id, query) = match s.find('?') {
+            Some(pos) => (&s[..pos], Some(&s[pos..])),
+            None => (s, None),
+        };
+
+        if !id.starts_with('&') || id[1..].to_sha256().is_err() {
+            return Err(Error::InvalidLink);
+        }
+
+        let key = match query {
+            Some(query) => {
+                let key = query
+                    .strip_prefix(UNBOX_QUERY)
+                    .and_then(|key| key.strip_suffix(BOXS_SUFFIX))
+                    .ok_or(Error::InvalidLink)?
+                    .replace("%2B", "+")
+                    .replace("%2F", "/")
+                    .replace("%3D", "=");
+                let key =
+                    secretbox::Key::from_slice(&base64::decode(&key)?).ok_or(Error::InvalidKey)?;
+                Some(key)
+            }
+            None => None,
+        };
+
+        Ok(BlobLink::new(id.to_string(), key))
+    }
+}
+
+fn increment_nonce(nonce: &mut secretbox::Nonce) {
+    for byte in nonce.0.iter_mut().rev() {
+        *byte = byte.wrapping_add(1);
+        if *byte != 0 {
+            break;
+        }
+    }
+}
+
+fn zero_nonce() -> secretbox::Nonce {
+    secretbox::Nonce([0u8; secretbox::NONCEBYTES])
+}
+
+/// Encrypt a blob with a new random key. Returns the encrypted blob and the
+/// link, including the key, to be used to reference it.
+pub fn box_blob(plaintext: &[u8]) -> (Vec<u8>, BlobLink) {
+    let key = secretbox::gen_key();
+    let mut nonce = zero_nonce();
+
+    let chunks = plaintext.len().div_ceil(MAX_CHUNK_LEN);
+    let mut boxed = Vec::with_capacity(plaintext.len() + (chunks + 1) * BOXED_HEADER_LEN);
+
+    for chunk in plaintext.chunks(MAX_CHUNK_LEN) {
+        let header_nonce = nonce;
+        increment_nonce(&mut nonce);
+
+        let mut body = chunk.to_vec();
+        let tag = secretbox::seal_detached(&mut body, &nonce, &key);
+        increment_nonce(&mut nonce);
+
+        let mut header = [0u8; HEADER_LEN];
+        header[..2].copy_from_slice(&(body.len() as u16).to_be_bytes());
+        header[2..].copy_from_slice(&tag.0);
+
+        boxed.extend_from_slice(&secretbox::seal(&header, &header_nonce, &key));
+        boxed.extend_from_slice(&body);
+    }
+    boxed.extend_from_slice(&secretbox::seal(&[0u8; HEADER_LEN], &nonce, &key));
+
+    let id = format!("&{}", sha256::hash(&boxed).to_ssb_id());
+    (boxed, BlobLink::new(id, Some(key)))
+}
+
+/// Decrypt a complete blob encrypted with `key`.
+pub fn unbox_blob(boxed: &[u8], key: &secretbox::Key) -> Result<Vec<u8>> {
+    let mut unboxer = BlobUnboxer::new(key.clone());
+    let plaintext = unboxer.push(boxed)?;
+    unboxer.finish()?;
+    Ok(plaintext)
+}
+
+/// Incremental decryption of a boxed blob, for the chunks received as
+/// responses to a `blobs.get` request.
+pub struct BlobUnboxer {
+    key: secretbox::Key,
+    nonce: secretbox::Nonce,
+    buffer: Vec<u8>,
+    pending: Option<(usize, secretbox::Tag)>,
+    finished: bool,
+}
+
+impl BlobUnboxer {
+    pub fn new(key: secretbox::Key) -> Self {
+        BlobUnboxer {
+            key,
+            nonce: zero_nonce(),
+            buffer: Vec::new(),
+            pending: None,
+            finished: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Push more boxed data, returning the plaintext that could be decrypted.
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<u8>> {
+        self.buffer.extend_from_slice(data);
+
+        let mut plaintext = Vec::new();
+        loop {
+            if self.finished {
+                if !self.buffer.is_empty() {
+                    return Err(Error::TrailingData);
+                }
+                break;
+            }
+            match self.pending.take() {
+                None => {
+                    if self.buffer.len() < BOXED_HEADER_LEN {
+                        break;
+                    }
+                    let header =
+                        secretbox::open(&self.buffer[..BOXED_HEADER_LEN], &self.nonce, &self.key)
+                            .map_err(|_| Error::FailedToDecipher)?;
+                    self.buffer.drain(..BOXED_HEADER_LEN);
+
+                    if header.iter().all(|b| *b == 0) {
+                        self.finished = true;
+                        continue;
+                    }
+                    let body_len = u16::from_be_bytes([header[0], header[1]]) as usize;
+                    let tag =
+                        secretbox::Tag::from_slice(&header[2..]).ok_or(Error::FailedToDecipher)?;
+                    self.pending = Some((body_len, tag));
+                }
+                Some((body_len, tag)) => {
+                    if self.buffer.len() < body_len {
+                        self.pending = Some((body_len, tag));
+                        break;
+                    }
+                    let mut body: Vec<u8> = self.buffer.drain(..body_len).collect();
+                    increment_nonce(&mut self.nonce);
+                    secretbox::open_detached(&mut body, &tag, &self.nonce, &self.key)
+                        .map_err(|_| Error::FailedToDecipher)?;
+                    increment_nonce(&mut self.nonce);
+                    plaintext.extend_from_slice(&body);
+                }
+            }
+        }
+
+        Ok(plaintext)
+    }
+
+    /// Check that the whole blob has been received.
+    pub fn finish(self) -> Result<()> {
+        if self.finished {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedEnd)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_box_unbox_blob() -> Result<()> {
+        let plaintext: Vec<u8> = (0..10000).map(|n| (n % 251) as u8).collect();
+        let (boxed, link) = box_blob(&plaintext);
+        assert_eq!(boxed.len(), plaintext.len() + 4 * BOXED_HEADER_LEN);
+
+        let link: BlobLink = link.to_string().parse()?;
+        assert_eq!(link.id, format!("&{}", sha256::hash(&boxed).to_ssb_id()));
+        let key = link.key.unwrap();
+        assert_eq!(unbox_blob(&boxed, &key)?, plaintext);
+
+        // received in arbitrary chunks
+        let mut unboxer = BlobUnboxer::new(key);
+        let mut unboxed = Vec::new();
+        for chunk in boxed.chunks(1000) {
+            unboxed.extend(unboxer.push(chunk)?);
+        }
+        unboxer.finish()?;
+        assert_eq!(unboxed, plaintext);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unbox_truncated_or_wrong_key() {
+        let (boxed, link) = box_blob(b"kuska");
+        let key = link.key.unwrap();
+        assert!(matches!(
+            unbox_blob(&boxed[..boxed.len() - 1], &key),
+            Err(Error::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            unbox_blob(&boxed, &secretbox::gen_key()),
+            Err(Error::FailedToDecipher)
+        ));
+    }
+
+    #[test]
+    fn test_parse_blob_link() -> Result<()> {
+        let id = "&uaGieSQDJcHfUp6hjIcIq55GoZh4Ug7tNmgaohoxrpw=.sha256";
+        let link: BlobLink = id.parse()?;
+        assert_eq!(link, BlobLink::new(id.to_string(), None));
+        assert_eq!(link.to_string(), id);
+
+        let unbox = format!(
+            "{}?unbox=ZE2%2FqsGCMqxyoMFsl1GbjxJKAhV8S2IuaNgAC2hmsc8%3D.boxs",
+            id
+        );
+        let link: BlobLink = unbox.parse()?;
+        assert_eq!(
+            link.query().unwrap(),
+            "?unbox=ZE2/qsGCMqxyoMFsl1GbjxJKAhV8S2IuaNgAC2hmsc8=.boxs"
+        );
+
+        assert!("%uaGieSQDJcHfUp6hjIcIq55GoZh4Ug7tNmgaohoxrpw=.sha256"
+            .parse::<BlobLink>()
+            .is_err());
+        Ok(())
+    }
+}
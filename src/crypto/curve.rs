@@ -0,0 +1,83 @@
+use kuska_sodiumoxide::crypto::{box_, scalarmult::curve25519, sealedbox, sign::ed25519};
+
+use super::{
+    error::{Error, Result},
+    ToSodiumObject,
+};
+
+/// Convert an ed25519 public key into a curve25519 public key.
+pub fn to_curve25519_pk(pk: &ed25519::PublicKey) -> Result<box_::PublicKey> {
+    box_::PublicKey::from_slice(&pk.to_curve25519()[..]).ok_or(Error::BadPublicKey)
+}
+
+/// Convert an ed25519 secret key into a curve25519 secret key.
+pub fn to_curve25519_sk(sk: &ed25519::SecretKey) -> Result<box_::SecretKey> {
+    box_::SecretKey::from_slice(&sk.to_curve25519()[..]).ok_or(Error::BadSecretKey)
+}
+
+/// Derive the diffie-hellman shared secret between our ed25519 secret key and
+/// the ed25519 public key of a peer. Both sides of the exchange derive the
+/// same secret.
+pub fn shared_secret(
+    sk: &ed25519::SecretKey,
+    peer_pk: &ed25519::PublicKey,
+) -> Result<curve25519::GroupElement> {
+    curve25519::scalarmult(&sk.to_curve25519(), &peer_pk.to_curve25519())
+        .map_err(|_| Error::ScalarMultFailed)
+}
+
+/// Encrypt `plaintext` into an anonymous sealed box that only the owner of
+/// `feed_id` can open.
+pub fn seal_to(plaintext: &[u8], feed_id: &str) -> Result<Vec<u8>> {
+    let pk = feed_id
+        .strip_prefix('@')
+        .ok_or(Error::BadPublicKey)?
+        .to_curve25519_pk()?;
+    Ok(sealedbox::seal(plaintext, &pk))
+}
+
+/// Open a sealed box created with `seal_to`.
+pub fn open_sealed(ciphertext: &[u8], sk: &ed25519::SecretKey) -> Result<Vec<u8>> {
+    let scalar = sk.to_curve25519();
+    let pk = box_::PublicKey::from_slice(&curve25519::scalarmult_base(&scalar)[..])
+        .ok_or(Error::BadPublicKey)?;
+    let sk = to_curve25519_sk(sk)?;
+    sealedbox::open(ciphertext, &pk, &sk).map_err(|_| Error::CannotOpenSealedBox)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keystore::OwnedIdentity;
+
+    #[test]
+    fn test_shared_secret() -> Result<()> {
+        let (a, b) = (OwnedIdentity::create(), OwnedIdentity::create());
+        assert_eq!(shared_secret(&a.sk, &b.pk)?, shared_secret(&b.sk, &a.pk)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_curve25519_keypair() -> Result<()> {
+        let id = OwnedIdentity::create();
+        let scalar = curve25519::Scalar::from_slice(&to_curve25519_sk(&id.sk)?[..]).unwrap();
+        assert_eq!(
+            &curve25519::scalarmult_base(&scalar)[..],
+            &to_curve25519_pk(&id.pk)?[..]
+        );
+        assert_eq!(id.id[1..].to_curve25519_pk()?, to_curve25519_pk(&id.pk)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sealed_box() -> Result<()> {
+        let (id, other) = (OwnedIdentity::create(), OwnedIdentity::create());
+        let ciphertext = seal_to(b"kuska", &id.id)?;
+        assert_eq!(open_sealed(&ciphertext, &id.sk)?, b"kuska");
+        assert!(matches!(
+            open_sealed(&ciphertext, &other.sk),
+            Err(Error::CannotOpenSealedBox)
+        ));
+        Ok(())
+    }
+}
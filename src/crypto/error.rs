@@ -14,6 +14,14 @@ pub enum Error {
     InvalidSuffix,
     #[error("cannot create signature")]
     CannotCreateSignature,
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("invalid json")]
+    InvalidJson,
+    #[error("crypto scalar mult failed")]
+    ScalarMultFailed,
+    #[error("cannot open sealed box")]
+    CannotOpenSealedBox,
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -1,8 +1,12 @@
+mod curve;
 mod error;
+mod sign;
 mod sodium;
 
+pub use curve::{open_sealed, seal_to, shared_secret, to_curve25519_pk, to_curve25519_sk};
 pub use error::{Error, Result};
-pub use kuska_sodiumoxide::crypto::{hash::sha256, sign::ed25519};
+pub use kuska_sodiumoxide::crypto::{box_, hash::sha256, scalarmult::curve25519, sign::ed25519};
+pub use sign::{sign_obj, verify_obj};
 pub use sodium::{
     ToSodiumObject, ToSsbId, CURVE_ED25519_SUFFIX, ED25519_SIGNATURE_SUFFIX, SHA256_SUFFIX,
 };
@@ -0,0 +1,120 @@
+use kuska_sodiumoxide::crypto::{auth, sign::ed25519};
+use serde_json::Value;
+
+use super::{
+    error::{Error, Result},
+    ToSodiumObject, ED25519_SIGNATURE_SUFFIX,
+};
+use crate::feed::stringify_json;
+
+const OBJ_SIGNATURE: &str = "signature";
+
+fn signable_bytes(obj: &Value, hmac_key: Option<&auth::Key>) -> Result<Vec<u8>> {
+    let text = stringify_json(obj).map_err(|_| Error::InvalidJson)?;
+    match hmac_key {
+        Some(key) => Ok(auth::authenticate(text.as_bytes(), key)[..].to_vec()),
+        None => Ok(text.into_bytes()),
+    }
+}
+
+/// Sign an arbitrary json object, like ssb-keys `signObj`. The signature is
+/// added to a copy of the object under the `signature` key.
+pub fn sign_obj(
+    sk: &ed25519::SecretKey,
+    hmac_key: Option<&auth::Key>,
+    obj: &Value,
+) -> Result<Value> {
+    let mut signed = match obj {
+        Value::Object(map) => map.clone(),
+        _ => return Err(Error::InvalidJson),
+    };
+
+    let signature = ed25519::sign_detached(&signable_bytes(obj, hmac_key)?, sk);
+    signed.insert(
+        OBJ_SIGNATURE.to_string(),
+        Value::String(format!(
+            "{}{}",
+            base64::encode(&signature),
+            ED25519_SIGNATURE_SUFFIX
+        )),
+    );
+
+    Ok(Value::Object(signed))
+}
+
+/// Verify a json object signed with `sign_obj`, like ssb-keys `verifyObj`.
+pub fn verify_obj(
+    pk: &ed25519::PublicKey,
+    hmac_key: Option<&auth::Key>,
+    obj: &Value,
+) -> Result<()> {
+    let mut unsigned = match obj {
+        Value::Object(map) => map.clone(),
+        _ => return Err(Error::InvalidJson),
+    };
+
+    let signature = match unsigned.remove(OBJ_SIGNATURE) {
+        Some(Value::String(signature)) => signature.to_ed25519_signature()?,
+        _ => return Err(Error::InvalidJson),
+    };
+
+    let signed_bytes = signable_bytes(&Value::Object(unsigned), hmac_key)?;
+    if !ed25519::verify_detached(&signature, &signed_bytes, pk) {
+        return Err(Error::InvalidSignature);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SK: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8DoQe/884Qvh1w3RjnS8CZZ+TWMJulDV8d3IZkElUxuA==.ed25519";
+    const PK: &str = "A6EHv/POEL4dcN0Y50vAmWfk1jCbpQ1fHdyGZBJVMbg=.ed25519";
+    const HMAC_KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+    const OBJ: &str = r#"{"type":"test","text":"kuska","nested":{"n":1,"list":[1,2]}}"#;
+    const SIGNATURE: &str = "xi6uGplmeKr2RwgMpgSSBr9TOj+uSe8AZpcVqbuLYIx7Z2nFoXGKoO+7IQUbltt0phVoVoYpNvSSpjheXVEBBQ==.sig.ed25519";
+    const HMAC_SIGNATURE: &str = "vUwDTGooziMsjB9GSSPW4J1U+FFzwQg+IK0MB6NIyQNsmwscasot4iFLwtSiFYzdeD1rReSqfscFbkfHYxufDQ==.sig.ed25519";
+
+    fn hmac_key() -> auth::Key {
+        auth::Key::from_slice(&base64::decode(HMAC_KEY).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_sign_obj_vectors() -> Result<()> {
+        let obj: Value = serde_json::from_str(OBJ).unwrap();
+        let sk = SK.to_ed25519_sk()?;
+        let pk = PK.to_ed25519_pk()?;
+
+        let signed = sign_obj(&sk, None, &obj)?;
+        assert_eq!(signed[OBJ_SIGNATURE], SIGNATURE);
+        verify_obj(&pk, None, &signed)?;
+
+        let signed = sign_obj(&sk, Some(&hmac_key()), &obj)?;
+        assert_eq!(signed[OBJ_SIGNATURE], HMAC_SIGNATURE);
+        verify_obj(&pk, Some(&hmac_key()), &signed)?;
+        assert!(matches!(
+            verify_obj(&pk, None, &signed),
+            Err(Error::InvalidSignature)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_obj_feed_message() -> Result<()> {
+        // feed messages are signed with ssb-keys `signObj`
+        let message = r#"{"previous":"%seUEAo7PTyA7vNwnOrmGIsUFfpyRzOvzGVv1QCb/Fz8=.sha256","author":"@BIbVppzlrNiRJogxDYz3glUS7G4s4D4NiXiPEAEzxdE=.ed25519","sequence":37,"timestamp":1439392020612,"hash":"sha256","content":{"type":"post","text":"@paul real time replies didn't work.","repliesTo":"%xWKunF6nXD7XMC+D4cjwDMZWmBnmRu69w9T25iLNa1Q=.sha256","mentions":["%7UKRfZb2u8al4tYWHqM55R9xpE/KKVh9U0M6BdugGt4=.sha256"],"recps":[{"link":"@hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519","name":"paul"}]},"signature":"gGxSPdBJZxp6x5f3HzQGoQSeSdh/C5AtymIn+miWa+lcC6DdqpRSgaeH9KHeLf+/CKhU6REYIpWaLr4CKDMfCg==.sig.ed25519"}"#;
+        let message: Value = serde_json::from_str(message).unwrap();
+        let author = "BIbVppzlrNiRJogxDYz3glUS7G4s4D4NiXiPEAEzxdE=.ed25519".to_ed25519_pk()?;
+        verify_obj(&author, None, &message)?;
+
+        let mut tampered = message;
+        tampered["sequence"] = Value::from(38);
+        assert!(matches!(
+            verify_obj(&author, None, &tampered),
+            Err(Error::InvalidSignature)
+        ));
+        Ok(())
+    }
+}
@@ -1,6 +1,9 @@
-use kuska_sodiumoxide::crypto::{hash::sha256, sign::ed25519};
+use kuska_sodiumoxide::crypto::{box_, hash::sha256, sign::ed25519};
 
-use super::error::{Error, Result};
+use super::{
+    curve::{to_curve25519_pk, to_curve25519_sk},
+    error::{Error, Result},
+};
 
 pub const CURVE_ED25519_SUFFIX: &str = ".ed25519";
 pub const ED25519_SIGNATURE_SUFFIX: &str = ".sig.ed25519";
@@ -13,6 +16,8 @@ pub trait ToSodiumObject {
     fn to_ed25519_sk_no_suffix(&self) -> Result<ed25519::SecretKey>;
     fn to_ed25519_signature(&self) -> Result<ed25519::Signature>;
     fn to_sha256(&self) -> Result<sha256::Digest>;
+    fn to_curve25519_pk(&self) -> Result<box_::PublicKey>;
+    fn to_curve25519_sk(&self) -> Result<box_::SecretKey>;
 }
 
 pub trait ToSsbId {
@@ -87,4 +92,12 @@ impl ToSodiumObject for str {
 
         ed25519::Signature::from_slice(&signature).ok_or(Error::CannotCreateSignature)
     }
+
+    fn to_curve25519_pk(self: &str) -> Result<box_::PublicKey> {
+        to_curve25519_pk(&self.to_ed25519_pk()?)
+    }
+
+    fn to_curve25519_sk(self: &str) -> Result<box_::SecretKey> {
+        to_curve25519_sk(&self.to_ed25519_sk()?)
+    }
 }
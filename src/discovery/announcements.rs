@@ -0,0 +1,241 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{
+    conn::{multiserver_address, PeerData, PeerDb, PeerSource},
+    error::{Error, Result},
+};
+use crate::{
+    api::dto::content::{PubAddress, TypedMessage},
+    crypto::{ed25519, ToSodiumObject},
+    feed::{Content, Message},
+};
+
+const MAX_HOSTNAME_LEN: usize = 253;
+
+static HOSTNAME_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?(\.[a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?)*\.?$")
+        .unwrap()
+});
+
+/// Validate the address of a `pub` message, returning its host, port and
+/// key. Loopback and unspecified ips are rejected, since they do not point
+/// to the pub for anyone else.
+pub fn parse_pub_address(address: &PubAddress) -> Result<(String, u16, ed25519::PublicKey)> {
+    let invalid = |reason: &str| Error::InvalidPubAddress(reason.to_string());
+
+    let host = address.host.as_deref().ok_or_else(|| invalid("no host"))?;
+    match host.parse::<IpAddr>() {
+        Ok(ip) if ip.is_loopback() || ip.is_unspecified() => return Err(invalid("local host")),
+        Ok(_) => {}
+        Err(_) if host.len() > MAX_HOSTNAME_LEN || !HOSTNAME_REGEX.is_match(host) => {
+            return Err(invalid("bad host"))
+        }
+        Err(_) if host.eq_ignore_ascii_case("localhost") => return Err(invalid("local host")),
+        Err(_) => {}
+    }
+    if address.port == 0 {
+        return Err(invalid("bad port"));
+    }
+    if !address.key.starts_with('@') {
+        return Err(invalid("bad key"));
+    }
+    let key = address.key[1..].to_ed25519_pk()?;
+
+    Ok((host.to_string(), address.port, key))
+}
+
+fn within(hops: &HashMap<String, i32>, max_hops: i32, feed: &str) -> bool {
+    matches!(hops.get(feed), Some(hops) if (0..=max_hops).contains(hops))
+}
+
+/// A pub and the feeds that announced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PubAnnouncement {
+    /// Multiserver address of the pub.
+    pub address: String,
+    /// Feed id of the pub.
+    pub key: String,
+    pub host: String,
+    pub port: u16,
+    pub announcers: BTreeSet<String>,
+}
+
+/// Pubs announced in `pub` messages, to bootstrap the connections from
+/// the ones announced by friends.
+#[derive(Debug, Default)]
+pub struct PubAnnouncements {
+    pubs: BTreeMap<String, PubAnnouncement>,
+}
+
+impl PubAnnouncements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a `pub` message, returns if it announced a new pub or a new
+    /// announcer of a known one. Invalid addresses are ignored.
+    pub fn apply(&mut self, msg: &Message) -> bool {
+        let address = match msg.typed_content() {
+            Content::Typed(TypedMessage::Pub {
+                address: Some(address),
+            }) => address,
+            _ => return false,
+        };
+        let (host, port, _) = match parse_pub_address(&address) {
+            Ok(parsed) => parsed,
+            Err(_) => return false,
+        };
+
+        let ms_address = multiserver_address(&host, port, &address.key);
+        let announcement = self
+            .pubs
+            .entry(ms_address.clone())
+            .or_insert_with(|| PubAnnouncement {
+                address: ms_address,
+                key: address.key,
+                host,
+                port,
+                announcers: BTreeSet::new(),
+            });
+        announcement.announcers.insert(msg.author().clone())
+    }
+
+    pub fn get(&self, address: &str) -> Option<&PubAnnouncement> {
+        self.pubs.get(address)
+    }
+
+    pub fn pubs(&self) -> impl Iterator<Item = &PubAnnouncement> {
+        self.pubs.values()
+    }
+
+    /// Pubs announced by a feed within `max_hops`, given the hops to each
+    /// feed, e.g. from `FriendsGraph::hops`.
+    pub fn announced_within<'a>(
+        &'a self,
+        hops: &'a HashMap<String, i32>,
+        max_hops: i32,
+    ) -> impl Iterator<Item = &'a PubAnnouncement> {
+        self.pubs().filter(move |announcement| {
+            announcement
+                .announcers
+                .iter()
+                .any(|announcer| within(hops, max_hops, announcer))
+        })
+    }
+
+    /// Merge the pubs announced within `max_hops` into the peer database,
+    /// recording their announcers within `max_hops`. Returns the number of
+    /// new peers.
+    pub fn merge_into(&self, db: &mut PeerDb, hops: &HashMap<String, i32>, max_hops: i32) -> usize {
+        let mut added = 0;
+        for announcement in self.announced_within(hops, max_hops) {
+            let announcers = announcement
+                .announcers
+                .iter()
+                .filter(|announcer| within(hops, max_hops, announcer))
+                .cloned();
+            match db.get_mut(&announcement.address) {
+                Some(peer) => {
+                    peer.announcers.extend(announcers);
+                    peer.announcers.sort_unstable();
+                    peer.announcers.dedup();
+                }
+                None => {
+                    let mut peer = PeerData::new(
+                        announcement.address.clone(),
+                        announcement.key.clone(),
+                        PeerSource::Pub,
+                    );
+                    peer.announcers = announcers.collect();
+                    db.add(peer);
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::keystore::OwnedIdentity;
+    use serde_json::json;
+
+    fn pub_address(host: Option<&str>, port: u16, key: &str) -> PubAddress {
+        PubAddress {
+            host: host.map(String::from),
+            port,
+            key: key.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse_pub_address() {
+        let key = OwnedIdentity::create().id;
+        assert!(parse_pub_address(&pub_address(Some("pub.example.com"), 8008, &key)).is_ok());
+        assert!(parse_pub_address(&pub_address(Some("10.0.0.1"), 8008, &key)).is_ok());
+        assert!(parse_pub_address(&pub_address(Some("::1"), 8008, &key)).is_err());
+        assert!(parse_pub_address(&pub_address(Some("localhost"), 8008, &key)).is_err());
+        assert!(parse_pub_address(&pub_address(Some("bad_host!"), 8008, &key)).is_err());
+        assert!(parse_pub_address(&pub_address(None, 8008, &key)).is_err());
+        assert!(parse_pub_address(&pub_address(Some("example.com"), 0, &key)).is_err());
+        assert!(parse_pub_address(&pub_address(Some("example.com"), 8008, &key[1..])).is_err());
+        assert!(
+            parse_pub_address(&pub_address(Some("example.com"), 8008, "@bad.ed25519")).is_err()
+        );
+    }
+
+    #[test]
+    fn test_pub_announcements() -> crate::feed::Result<()> {
+        let (alice, bob, carol) = (
+            OwnedIdentity::create(),
+            OwnedIdentity::create(),
+            OwnedIdentity::create(),
+        );
+        let pub_key = OwnedIdentity::create().id;
+        let announce = |prev, id, host: &str| {
+            let content = json!({
+                "type": "pub",
+                "address": { "host": host, "port": 8008, "key": pub_key },
+            });
+            Message::sign(prev, id, content)
+        };
+        let a1 = announce(None, &alice, "pub.example.com")?;
+        let b1 = announce(None, &bob, "pub.example.com")?;
+        let b2 = announce(Some(&b1), &bob, "127.0.0.1")?;
+        let c1 = announce(None, &carol, "other.example.com")?;
+
+        let mut index = PubAnnouncements::new();
+        assert!(index.apply(&a1));
+        assert!(index.apply(&b1));
+        assert!(!index.apply(&b1));
+        assert!(!index.apply(&b2));
+        assert!(index.apply(&c1));
+        assert_eq!(index.pubs().count(), 2);
+
+        let address = multiserver_address("pub.example.com", 8008, &pub_key);
+        let announcement = index.get(&address).unwrap();
+        assert_eq!(announcement.key, pub_key);
+        assert_eq!(announcement.announcers.len(), 2);
+
+        let hops: HashMap<_, _> = [(alice.id.clone(), 1), (carol.id.clone(), 3)]
+            .into_iter()
+            .collect();
+        let mut db = PeerDb::new();
+        assert_eq!(index.merge_into(&mut db, &hops, 2), 1);
+        assert_eq!(index.merge_into(&mut db, &hops, 2), 0);
+        let peer = db.get(&address).unwrap();
+        assert_eq!(peer.source, PeerSource::Pub);
+        // bob announced it too, but is not within the hops
+        assert_eq!(peer.announcers, vec![alice.id.clone()]);
+        assert_eq!(index.merge_into(&mut db, &hops, 3), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use futures::channel::mpsc;
+
+use super::error::Result;
+
+/// Default number of connections to hold.
+pub const DEFAULT_TARGET_CONNECTIONS: usize = 3;
+/// Default wait after a first failure, doubled after each other one.
+pub const DEFAULT_BACKOFF_BASE: Duration = Duration::from_secs(5);
+/// Default maximum wait after failures.
+pub const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// Multiserver address of a peer, e.g. `net:example.com:8008~shs:<key>`.
+pub fn multiserver_address(host: &str, port: u16, key: &str) -> String {
+    let key = key.trim_start_matches('@').trim_end_matches(".ed25519");
+    format!("net:{}:{}~shs:{}", host, port, key)
+}
+
+/// Where a peer was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerSource {
+    /// LAN discovery.
+    Local,
+    Manual,
+    Invite,
+    /// A pub announced in a feed.
+    Pub,
+}
+
+/// A peer in the database, like the entries of ssb-conn `conn.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerData {
+    /// Skipped in `conn.json`, where it is the key of the entry.
+    #[serde(skip)]
+    pub address: String,
+    /// Feed id of the peer.
+    pub key: String,
+    pub source: PeerSource,
+    /// Milliseconds since the unix epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_success: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_failure: Option<u64>,
+    /// Failures since the last success.
+    #[serde(default)]
+    pub failures: u32,
+    /// Feeds that announced the peer, for pubs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub announcers: Vec<String>,
+}
+
+impl PeerData {
+    pub fn new(address: String, key: String, source: PeerSource) -> Self {
+        PeerData {
+            address,
+            key,
+            source,
+            last_success: None,
+            last_failure: None,
+            failures: 0,
+            announcers: Vec::new(),
+        }
+    }
+}
+
+/// The known peers by address, persisted like ssb-conn `conn.json`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PeerDb(BTreeMap<String, PeerData>);
+
+impl PeerDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load the database from `path`, empty if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if !path.as_ref().exists() {
+            return Ok(Self::new());
+        }
+        let mut db: PeerDb = serde_json::from_slice(&fs::read(path)?)?;
+        for (address, peer) in db.0.iter_mut() {
+            peer.address = address.clone();
+        }
+        Ok(db)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Add a peer, returns false if its address was already known.
+    pub fn add(&mut self, peer: PeerData) -> bool {
+        if self.0.contains_key(&peer.address) {
+            return false;
+        }
+        self.0.insert(peer.address.clone(), peer);
+        true
+    }
+
+    pub fn get(&self, address: &str) -> Option<&PeerData> {
+        self.0.get(address)
+    }
+
+    pub fn get_mut(&mut self, address: &str) -> Option<&mut PeerData> {
+        self.0.get_mut(address)
+    }
+
+    pub fn remove(&mut self, address: &str) -> Option<PeerData> {
+        self.0.remove(address)
+    }
+
+    pub fn peers(&self) -> impl Iterator<Item = &PeerData> {
+        self.0.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    Connecting,
+    Connected,
+}
+
+/// A change in the connection to a peer, by address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnEvent {
+    Connecting(String),
+    Connected(String),
+    Failed(String),
+    Disconnected(String),
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Decides which peers to connect to, like ssb-conn: it holds a target
+/// number of connections, preferring the peers closest in hops, and
+/// retries failing peers with exponential backoff.
+///
+/// The connections themselves are made by the caller, which reports how
+/// they went.
+pub struct ConnManager {
+    db: PeerDb,
+    hops: HashMap<String, i32>,
+    target: usize,
+    backoff_base: Duration,
+    backoff_max: Duration,
+    states: HashMap<String, ConnState>,
+    subscribers: Vec<mpsc::UnboundedSender<ConnEvent>>,
+    clock: Box<dyn Fn() -> u64 + Send>,
+}
+
+impl ConnManager {
+    pub fn new(db: PeerDb) -> Self {
+        ConnManager {
+            db,
+            hops: HashMap::new(),
+            target: DEFAULT_TARGET_CONNECTIONS,
+            backoff_base: DEFAULT_BACKOFF_BASE,
+            backoff_max: DEFAULT_BACKOFF_MAX,
+            states: HashMap::new(),
+            subscribers: Vec::new(),
+            clock: Box::new(now_millis),
+        }
+    }
+
+    /// Number of connections to hold.
+    pub fn target(self, target: usize) -> Self {
+        Self { target, ..self }
+    }
+
+    pub fn backoff(self, backoff_base: Duration, backoff_max: Duration) -> Self {
+        Self {
+            backoff_base,
+            backoff_max,
+            ..self
+        }
+    }
+
+    /// Source of the current time in milliseconds since the unix epoch,
+    /// the system clock by default.
+    pub fn clock<F: Fn() -> u64 + Send + 'static>(self, clock: F) -> Self {
+        Self {
+            clock: Box::new(clock),
+            ..self
+        }
+    }
+
+    pub fn db(&self) -> &PeerDb {
+        &self.db
+    }
+
+    pub fn db_mut(&mut self) -> &mut PeerDb {
+        &mut self.db
+    }
+
+    /// Set the hops to each feed, e.g. from `FriendsGraph::hops`. Peers at
+    /// fewer hops are preferred, and blocked ones (negative hops) skipped.
+    pub fn set_hops(&mut self, hops: HashMap<String, i32>) {
+        self.hops = hops;
+    }
+
+    /// Stream of the connection events from now on.
+    pub fn events(&mut self) -> mpsc::UnboundedReceiver<ConnEvent> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    fn emit(&mut self, event: ConnEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+
+    pub fn state(&self, address: &str) -> Option<ConnState> {
+        self.states.get(address).copied()
+    }
+
+    /// Number of connecting and connected peers.
+    pub fn active(&self) -> usize {
+        self.states.len()
+    }
+
+    /// When the peer can be tried again after its failures.
+    pub fn next_attempt(&self, peer: &PeerData) -> u64 {
+        match (peer.failures, peer.last_failure) {
+            (0, _) | (_, None) => 0,
+            (failures, Some(last_failure)) => {
+                let factor = 1u64 << (failures - 1).min(32);
+                let base = self.backoff_base.as_millis() as u64;
+                let backoff = base
+                    .saturating_mul(factor)
+                    .min(self.backoff_max.as_millis() as u64);
+                last_failure.saturating_add(backoff)
+            }
+        }
+    }
+
+    /// Pick the peers to connect to now to reach the target, best first.
+    /// They are marked as connecting until reported as connected or failed.
+    pub fn schedule(&mut self) -> Vec<PeerData> {
+        let slots = self.target.saturating_sub(self.active());
+        if slots == 0 {
+            return Vec::new();
+        }
+        let now = (self.clock)();
+        let mut candidates: Vec<_> = self
+            .db
+            .peers()
+            .filter(|peer| !self.states.contains_key(&peer.address))
+            .filter(|peer| !matches!(self.hops.get(&peer.key), Some(hops) if *hops < 0))
+            .filter(|peer| self.next_attempt(peer) <= now)
+            .collect();
+        candidates.sort_by_key(|peer| {
+            (
+                self.hops.get(&peer.key).copied().unwrap_or(i32::MAX),
+                peer.source,
+                peer.failures,
+                Reverse(peer.last_success),
+            )
+        });
+        let scheduled: Vec<PeerData> = candidates.into_iter().take(slots).cloned().collect();
+
+        for peer in &scheduled {
+            self.states
+                .insert(peer.address.clone(), ConnState::Connecting);
+            self.emit(ConnEvent::Connecting(peer.address.clone()));
+        }
+        scheduled
+    }
+
+    /// Report a successful connection, also for incoming ones.
+    pub fn connected(&mut self, address: &str) {
+        let now = (self.clock)();
+        if let Some(peer) = self.db.get_mut(address) {
+            peer.last_success = Some(now);
+            peer.failures = 0;
+        }
+        self.states
+            .insert(address.to_string(), ConnState::Connected);
+        self.emit(ConnEvent::Connected(address.to_string()));
+    }
+
+    /// Report a failed connection attempt.
+    pub fn failed(&mut self, address: &str) {
+        let now = (self.clock)();
+        if let Some(peer) = self.db.get_mut(address) {
+            peer.last_failure = Some(now);
+            peer.failures += 1;
+        }
+        self.states.remove(address);
+        self.emit(ConnEvent::Failed(address.to_string()));
+    }
+
+    pub fn disconnected(&mut self, address: &str) {
+        if self.states.remove(address).is_some() {
+            self.emit(ConnEvent::Disconnected(address.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::StreamExt;
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    #[test]
+    fn test_peer_db() -> Result<()> {
+        let mut db = PeerDb::new();
+        let address = multiserver_address("example.com", 8008, "@a2V5=.ed25519");
+        assert_eq!(address, "net:example.com:8008~shs:a2V5=");
+        assert!(db.add(PeerData::new(
+            address.clone(),
+            "@a2V5=.ed25519".to_string(),
+            PeerSource::Pub
+        )));
+        assert!(!db.add(PeerData::new(
+            address.clone(),
+            "@other".to_string(),
+            PeerSource::Manual
+        )));
+        db.get_mut(&address).unwrap().failures = 2;
+
+        let path = std::env::temp_dir().join(format!("kuska-conn-{}.json", std::process::id()));
+        db.save(&path)?;
+        let json: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)?;
+        assert_eq!(json[&address]["source"], "pub");
+        assert_eq!(json[&address]["failures"], 2);
+        let loaded = PeerDb::load(&path)?;
+        fs::remove_file(&path)?;
+        assert_eq!(loaded, db);
+        assert!(PeerDb::load(&path)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_conn_manager() {
+        let peer = |name: &str, source| {
+            PeerData::new(format!("net:{}", name), format!("@{}", name), source)
+        };
+        let mut db = PeerDb::new();
+        db.add(peer("friend", PeerSource::Pub));
+        db.add(peer("foaf", PeerSource::Pub));
+        db.add(peer("blocked", PeerSource::Local));
+        db.add(peer("stranger", PeerSource::Local));
+
+        let time = Arc::new(AtomicU64::new(1_000_000));
+        let clock = time.clone();
+        let mut manager = ConnManager::new(db)
+            .target(2)
+            .backoff(Duration::from_secs(10), Duration::from_secs(15))
+            .clock(move || clock.load(Ordering::SeqCst));
+        let events = manager.events();
+        manager.set_hops(
+            [("@friend", 1), ("@foaf", 2), ("@blocked", -1)]
+                .iter()
+                .map(|(key, hops)| (key.to_string(), *hops))
+                .collect(),
+        );
+        let addresses = |peers: Vec<PeerData>| -> Vec<String> {
+            peers.into_iter().map(|p| p.address).collect()
+        };
+
+        assert_eq!(
+            addresses(manager.schedule()),
+            vec!["net:friend", "net:foaf"]
+        );
+        assert!(manager.schedule().is_empty());
+
+        manager.connected("net:friend");
+        manager.failed("net:foaf");
+        assert_eq!(manager.state("net:friend"), Some(ConnState::Connected));
+        // foaf is backing off
+        assert_eq!(addresses(manager.schedule()), vec!["net:stranger"]);
+        manager.failed("net:stranger");
+
+        time.fetch_add(10_000, Ordering::SeqCst);
+        assert_eq!(addresses(manager.schedule()), vec!["net:foaf"]);
+        manager.failed("net:foaf");
+        let foaf = manager.db().get("net:foaf").unwrap();
+        assert_eq!(foaf.failures, 2);
+        // capped by the maximum backoff
+        assert_eq!(manager.next_attempt(foaf), 1_010_000 + 15_000);
+
+        manager.disconnected("net:friend");
+        assert_eq!(manager.active(), 0);
+        assert_eq!(
+            manager.db().get("net:friend").unwrap().last_success,
+            Some(1_000_000)
+        );
+
+        drop(manager);
+        let events: Vec<_> = async_std::task::block_on(events.collect());
+        assert_eq!(events.len(), 9);
+        assert_eq!(events[8], ConnEvent::Disconnected("net:friend".to_string()));
+    }
+}
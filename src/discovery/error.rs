@@ -8,8 +8,12 @@ pub enum Error {
     InvalidInviteCode,
     #[error("invalid broadcast message")]
     InvalidBroadcastMessage,
+    #[error("invalid pub address: {0}")]
+    InvalidPubAddress(String),
     #[error("invalid crypto format")]
     CryptoFormat(#[from] crate::crypto::Error),
+    #[error("json")]
+    Json(#[from] serde_json::Error),
     #[error("i/o")]
     Io(#[from] std::io::Error),
 }
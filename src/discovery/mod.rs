@@ -1,3 +1,5 @@
+mod announcements;
+mod conn;
 mod error;
 mod lan;
 mod network;
@@ -5,6 +7,11 @@ mod pubs;
 
 pub use error::{Error, Result};
 
+pub use announcements::{parse_pub_address, PubAnnouncement, PubAnnouncements};
+pub use conn::{
+    multiserver_address, ConnEvent, ConnManager, ConnState, PeerData, PeerDb, PeerSource,
+    DEFAULT_BACKOFF_BASE, DEFAULT_BACKOFF_MAX, DEFAULT_TARGET_CONNECTIONS,
+};
 pub use lan::LanBroadcast;
 pub use network::ssb_net_id;
 pub use pubs::Invite;
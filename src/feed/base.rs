@@ -26,14 +26,14 @@ impl Feed {
         Message::from_value(self.value)
     }
     pub fn new(m: Message) -> Self {
-        let key = format!("%{}.sha256", base64::encode(&ssb_sha256(&m.value).unwrap()));
+        let key = m.id().to_string();
         let timestamp = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_secs_f64();
         Feed {
             key,
-            value: m.value,
+            value: m.into_value().into_json(),
             timestamp,
             rts: None,
         }
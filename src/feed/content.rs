@@ -0,0 +1,195 @@
+use std::{any::Any, collections::HashMap, fmt, sync::Arc};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::is_privatebox;
+use crate::api::dto::content::TypedMessage;
+
+type Decoder = Arc<dyn Fn(&Value) -> Option<Arc<dyn Any + Send + Sync>> + Send + Sync>;
+
+/// The content of a feed message.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone)]
+pub enum Content {
+    /// Content of a known message type.
+    Typed(TypedMessage),
+    /// Content of a type registered in a `ContentRegistry`.
+    Custom(CustomContent),
+    /// Content that is not encrypted, but cannot be decoded as a known type.
+    Raw(Value),
+    /// Private box encrypted content.
+    Encrypted(String),
+}
+
+impl Content {
+    /// Decode content using only the built-in message types.
+    pub fn from_value(v: &Value) -> Self {
+        ContentRegistry::new().decode(v)
+    }
+
+    /// The `type` field of the content, if it is not encrypted.
+    pub fn xtype(&self) -> Option<&str> {
+        match self {
+            Content::Typed(typed) => Some(typed.xtype()),
+            Content::Custom(custom) => Some(custom.xtype()),
+            Content::Raw(v) => v.get("type").and_then(Value::as_str),
+            Content::Encrypted(_) => None,
+        }
+    }
+}
+
+/// Content decoded by an application supplied decoder.
+#[derive(Clone)]
+pub struct CustomContent {
+    xtype: String,
+    data: Arc<dyn Any + Send + Sync>,
+}
+
+impl CustomContent {
+    pub fn xtype(&self) -> &str {
+        &self.xtype
+    }
+
+    /// Get the decoded content if it is of type `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.data.downcast_ref::<T>()
+    }
+}
+
+impl fmt::Debug for CustomContent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomContent")
+            .field("xtype", &self.xtype)
+            .finish()
+    }
+}
+
+/// A set of decoders for message `type`s not known by kuska-ssb.
+///
+/// Registered types take precedence over the built-in `TypedMessage` ones,
+/// content that no decoder accepts is returned as `Content::Raw`.
+#[derive(Default, Clone)]
+pub struct ContentRegistry {
+    decoders: HashMap<String, Decoder>,
+}
+
+impl ContentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a `type` decoded with serde into `T`.
+    pub fn register<T>(self, xtype: &str) -> Self
+    where
+        T: DeserializeOwned + Any + Send + Sync,
+    {
+        self.register_with(xtype, |v| serde_json::from_value::<T>(v.clone()).ok())
+    }
+
+    /// Register a `type` with a custom decoder, returning `None` if the
+    /// content is not valid.
+    pub fn register_with<T, F>(mut self, xtype: &str, decoder: F) -> Self
+    where
+        T: Any + Send + Sync,
+        F: Fn(&Value) -> Option<T> + Send + Sync + 'static,
+    {
+        let decoder: Decoder = Arc::new(move |v: &Value| {
+            decoder(v).map(|data| Arc::new(data) as Arc<dyn Any + Send + Sync>)
+        });
+        self.decoders.insert(xtype.to_string(), decoder);
+        self
+    }
+
+    pub fn is_registered(&self, xtype: &str) -> bool {
+        self.decoders.contains_key(xtype)
+    }
+
+    /// Decode the content of a message.
+    pub fn decode(&self, v: &Value) -> Content {
+        if let Value::String(text) = v {
+            if is_privatebox(text) {
+                return Content::Encrypted(text.clone());
+            }
+        }
+
+        let decoder = v
+            .get("type")
+            .and_then(Value::as_str)
+            .and_then(|xtype| self.decoders.get_key_value(xtype));
+        if let Some((xtype, decoder)) = decoder {
+            return match decoder(v) {
+                Some(data) => Content::Custom(CustomContent {
+                    xtype: xtype.clone(),
+                    data,
+                }),
+                None => Content::Raw(v.clone()),
+            };
+        }
+
+        match serde_json::from_value(v.clone()) {
+            Ok(typed) => Content::Typed(typed),
+            Err(_) => Content::Raw(v.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct GitRepo {
+        name: String,
+    }
+
+    #[test]
+    fn test_content_from_value() {
+        let post = serde_json::json!({ "type": "post", "text": "hola" });
+        assert!(matches!(
+            Content::from_value(&post),
+            Content::Typed(TypedMessage::Post { .. })
+        ));
+
+        let unknown = serde_json::json!({ "type": "git-update" });
+        assert!(matches!(Content::from_value(&unknown), Content::Raw(_)));
+
+        let boxed = Value::String("c2VjcmV0.box".to_string());
+        assert!(matches!(Content::from_value(&boxed), Content::Encrypted(_)));
+    }
+
+    #[test]
+    fn test_content_registry() {
+        let registry = ContentRegistry::new()
+            .register::<GitRepo>("git-repo")
+            .register_with("npm-packages", |v| v.get("mentions").map(|m| m.to_string()));
+        assert!(registry.is_registered("git-repo"));
+
+        let repo = serde_json::json!({ "type": "git-repo", "name": "kuska" });
+        match registry.decode(&repo) {
+            Content::Custom(custom) => {
+                assert_eq!(custom.xtype(), "git-repo");
+                assert_eq!(
+                    custom.downcast_ref::<GitRepo>(),
+                    Some(&GitRepo {
+                        name: "kuska".to_string()
+                    })
+                );
+                assert_eq!(custom.downcast_ref::<String>(), None);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let npm = serde_json::json!({ "type": "npm-packages", "mentions": [] });
+        assert!(matches!(registry.decode(&npm), Content::Custom(_)));
+
+        // registered but invalid, built-in, and unknown types
+        let invalid = serde_json::json!({ "type": "git-repo" });
+        assert!(matches!(registry.decode(&invalid), Content::Raw(_)));
+        let post = serde_json::json!({ "type": "post", "text": "hola" });
+        assert!(matches!(registry.decode(&post), Content::Typed(_)));
+        let unknown = serde_json::json!({ "type": "git-update" });
+        assert!(matches!(registry.decode(&unknown), Content::Raw(_)));
+        assert_eq!(registry.decode(&unknown).xtype(), Some("git-update"));
+    }
+}
@@ -1,13 +1,15 @@
 use std::{str::FromStr, time::SystemTime};
 
-use kuska_sodiumoxide::crypto::sign::ed25519;
 use serde_json::Value;
 
 use super::{
     error::{Error, Result},
-    ssb_sha256, stringify_json,
+    ssb_sha256, Content, ContentRegistry,
+};
+use crate::{
+    crypto::{sign_obj, verify_obj, ToSodiumObject},
+    keystore::OwnedIdentity,
 };
-use crate::{crypto::ToSodiumObject, keystore::OwnedIdentity};
 use kuska_sodiumoxide::crypto::hash::sha256;
 
 const MSG_PREVIOUS: &str = "previous";
@@ -38,6 +40,7 @@ macro_rules! cast_opt {
     };
 }
 
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct MessageId(sha256::Digest);
 impl ToString for MessageId {
     fn to_string(&self) -> String {
@@ -52,13 +55,152 @@ impl AsRef<[u8]> for MessageId {
     }
 }
 
+/// The signed value of a feed message, with its fields already type checked.
+///
+/// The original json is kept since the message id and the signature depend
+/// on the order of its keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Value", into = "Value")]
+pub struct MessageValue {
+    previous: Option<String>,
+    author: String,
+    sequence: u64,
+    timestamp: f64,
+    hash: String,
+    signature: String,
+    value: Value,
+}
+
+impl MessageValue {
+    pub fn from_value(v: Value) -> Result<Self> {
+        let v = cast!(Some(v), Value::Object)?;
+
+        let previous = cast_opt!(v.get(MSG_PREVIOUS), Value::String)?.cloned();
+        let author = cast!(v.get(MSG_AUTHOR), Value::String)?.clone();
+        let sequence = cast!(v.get(MSG_SEQUENCE), Value::Number)?
+            .as_u64()
+            .ok_or(Error::InvalidJson)?;
+        let timestamp = cast!(v.get(MSG_TIMESTAMP), Value::Number)?
+            .as_f64()
+            .ok_or(Error::InvalidJson)?;
+        let hash = cast!(v.get(MSG_HASH), Value::String)?.clone();
+        let signature = cast!(v.get(MSG_SIGNATURE), Value::String)?.clone();
+        v.get(MSG_CONTENT).ok_or(Error::InvalidJson)?;
+
+        if !author.starts_with('@') {
+            return Err(Error::InvalidJson);
+        }
+
+        Ok(MessageValue {
+            previous,
+            author,
+            sequence,
+            timestamp,
+            hash,
+            signature,
+            value: Value::Object(v),
+        })
+    }
+
+    pub fn previous(&self) -> Option<&String> {
+        self.previous.as_ref()
+    }
+
+    pub fn author(&self) -> &String {
+        &self.author
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn timestamp(&self) -> f64 {
+        self.timestamp
+    }
+
+    pub fn hash(&self) -> &String {
+        &self.hash
+    }
+
+    pub fn content(&self) -> &Value {
+        &self.value[MSG_CONTENT]
+    }
+
+    pub fn signature(&self) -> &String {
+        &self.signature
+    }
+
+    /// The original json of the message.
+    pub fn as_json(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_json(self) -> Value {
+        self.value
+    }
+}
+
+impl PartialEq for MessageValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl Eq for MessageValue {}
+
+impl TryFrom<Value> for MessageValue {
+    type Error = Error;
+
+    fn try_from(v: Value) -> Result<Self> {
+        MessageValue::from_value(v)
+    }
+}
+
+impl From<MessageValue> for Value {
+    fn from(v: MessageValue) -> Self {
+        v.value
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageJson {
+    value: Value,
+}
+
+impl TryFrom<MessageJson> for Message {
+    type Error = Error;
+
+    fn try_from(v: MessageJson) -> Result<Self> {
+        Message::from_value(v.value)
+    }
+}
+
+/// A feed message whose signature has been verified.
 #[derive(Debug, Deserialize, Clone, Eq, PartialEq)]
+#[serde(try_from = "MessageJson")]
 pub struct Message {
-    pub value: serde_json::Value,
+    id: MessageId,
+    value: MessageValue,
 }
 
 impl Message {
+    /// Sign a new message of `identity` after `prev`, timestamped now.
     pub fn sign(prev: Option<&Message>, identity: &OwnedIdentity, content: Value) -> Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis() as u64;
+        Self::sign_at(prev, identity, content, timestamp)
+    }
+
+    /// Sign a new message of `identity` after `prev`, with the given
+    /// timestamp in milliseconds since the unix epoch. For deterministic
+    /// timestamps, e.g. in tests.
+    pub fn sign_at(
+        prev: Option<&Message>,
+        identity: &OwnedIdentity,
+        content: Value,
+        timestamp: u64,
+    ) -> Result<Self> {
         let mut value: serde_json::Map<String, Value> = serde_json::Map::new();
         if let Some(prev) = prev {
             value.insert(
@@ -77,10 +219,6 @@ impl Message {
             );
         }
 
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_millis() as u64;
-
         let timestamp = Value::Number(serde_json::Number::from(timestamp));
 
         value.insert(MSG_AUTHOR.to_string(), Value::String(identity.id.clone()));
@@ -88,19 +226,9 @@ impl Message {
         value.insert(MSG_HASH.to_string(), Value::String("sha256".to_string()));
         value.insert(MSG_CONTENT.to_string(), content);
 
-        let value = Value::Object(value);
-        let to_sign_text = stringify_json(&value)?;
-        let mut value = cast!(Some(value), Value::Object)?;
+        let value = sign_obj(&identity.sk, None, &Value::Object(value))?;
 
-        let signature = ed25519::sign_detached(to_sign_text.as_bytes(), &identity.sk);
-        value.insert(
-            MSG_SIGNATURE.to_string(),
-            Value::String(format!("{}.sig.ed25519", base64::encode(&signature))),
-        );
-
-        Ok(Message {
-            value: Value::Object(value),
-        })
+        Self::from_message_value(MessageValue::from_value(value)?)
     }
 
     pub fn from_slice(s: &[u8]) -> Result<Self> {
@@ -108,72 +236,69 @@ impl Message {
     }
 
     pub fn from_value(v: Value) -> Result<Self> {
-        let mut v = cast!(Some(v), Value::Object)?;
-
-        // check if ok
-        cast_opt!(v.get(MSG_PREVIOUS), Value::String)?;
-        cast!(v.get(MSG_SEQUENCE), Value::Number)?;
-        cast!(v.get(MSG_SEQUENCE), Value::Number)?;
-        cast!(v.get(MSG_TIMESTAMP), Value::Number)?;
-        cast!(v.get(MSG_HASH), Value::String)?;
-        v.get(MSG_CONTENT).ok_or(Error::InvalidJson)?;
+        Self::from_message_value(MessageValue::from_value(v)?)
+    }
 
-        // verify signature
-        let signature = cast!(v.remove(MSG_SIGNATURE), Value::String)?;
-        let author = cast!(v.get(MSG_AUTHOR), Value::String)?;
-        let sig = signature.to_ed25519_signature()?;
-        let signer = author[1..].to_ed25519_pk()?;
+    /// Verify the signature of a message value.
+    pub fn from_message_value(value: MessageValue) -> Result<Self> {
+        let signer = value.author()[1..].to_ed25519_pk()?;
+        verify_obj(&signer, None, value.as_json()).map_err(|err| match err {
+            crate::crypto::Error::InvalidSignature => Error::InvalidSignature,
+            err => Error::CryptoFormat(err),
+        })?;
 
-        let value = Value::Object(v);
-        let signed_text = stringify_json(&value)?;
-        if !ed25519::verify_detached(&sig, signed_text.as_ref(), &signer) {
-            return Err(Error::InvalidSignature);
-        }
+        let id = MessageId(ssb_sha256(value.as_json())?);
+        Ok(Message { id, value })
+    }
 
-        // put signature back
-        let mut v = cast!(Some(value), Value::Object)?;
-        v.insert(MSG_SIGNATURE.to_string(), Value::String(signature));
+    pub fn id(&self) -> &MessageId {
+        &self.id
+    }
 
-        Ok(Message {
-            value: Value::Object(v),
-        })
+    pub fn value(&self) -> &MessageValue {
+        &self.value
     }
 
-    pub fn id(&self) -> MessageId {
-        MessageId(ssb_sha256(&self.value).unwrap())
+    pub fn into_value(self) -> MessageValue {
+        self.value
     }
 
     pub fn previous(&self) -> Option<&String> {
-        cast_opt!(self.value.get(MSG_PREVIOUS), Value::String).unwrap()
+        self.value.previous()
     }
+
     pub fn author(&self) -> &String {
-        cast!(self.value.get(MSG_AUTHOR), Value::String).unwrap()
+        self.value.author()
     }
 
     pub fn sequence(&self) -> u64 {
-        cast!(self.value.get(MSG_SEQUENCE), Value::Number)
-            .unwrap()
-            .as_u64()
-            .unwrap()
+        self.value.sequence()
     }
 
     pub fn timestamp(&self) -> f64 {
-        cast!(self.value.get(MSG_TIMESTAMP), Value::Number)
-            .unwrap()
-            .as_f64()
-            .unwrap()
+        self.value.timestamp()
     }
 
     pub fn hash(&self) -> &String {
-        cast!(self.value.get(MSG_HASH), Value::String).unwrap()
+        self.value.hash()
     }
 
     pub fn content(&self) -> &Value {
-        self.value.get(MSG_CONTENT).unwrap()
+        self.value.content()
+    }
+
+    /// Decode the content into a `TypedMessage` if possible.
+    pub fn typed_content(&self) -> Content {
+        Content::from_value(self.content())
+    }
+
+    /// Decode the content, also trying the types known by `registry`.
+    pub fn typed_content_with(&self, registry: &ContentRegistry) -> Content {
+        registry.decode(self.content())
     }
 
     pub fn signature(&self) -> &String {
-        cast!(self.value.get(MSG_SIGNATURE), Value::String).unwrap()
+        self.value.signature()
     }
 }
 
@@ -187,7 +312,7 @@ impl FromStr for Message {
 
 impl ToString for Message {
     fn to_string(&self) -> String {
-        serde_json::to_string(&self.value).unwrap()
+        self.value.as_json().to_string()
     }
 }
 
@@ -211,7 +336,37 @@ mod test {
         let msg1 = Message::sign(None, &id, content.clone())?.to_string();
         let msg1 = Message::from_str(&msg1)?;
         let msg2 = Message::sign(Some(&msg1), &id, content)?.to_string();
-        Message::from_str(&msg2)?;
+        let msg2 = Message::from_str(&msg2)?;
+        assert_eq!(msg2.previous(), Some(&msg1.id().to_string()));
+        assert_eq!(msg2.sequence(), 2);
         Ok(())
     }
+
+    #[test]
+    fn test_message_value_keeps_key_order() -> Result<()> {
+        let message = r#"{"previous":"%ButTjV+H9VfONhX+lLbJb5LR+W14SFqbmjOfdMPZ5+4=.sha256","sequence":15034,"author":"@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519","timestamp":1567190273951.0159,"hash":"sha256","content":{"type":"vote","channel":null,"vote":{"link":"%GvtUsekEwsCj1cQ6+4Gihkm+ek99BhB537g1xUKjhsA=.sha256","value":1,"expression":"Like"}},"signature":"UkVfqDmBhHrDfMvFT8iUhEispAku/zbdXKCyRVlxYp2wNtJ4okwKE7hTkKhbiMVA7sGIV5dzHZyMotXCL46iDw==.sig.ed25519"}"#;
+        let value: MessageValue = serde_json::from_str(message)?;
+        assert_eq!(value.sequence(), 15034);
+        assert_eq!(serde_json::to_string(&value)?, message);
+
+        let msg = Message::from_message_value(value)?;
+        assert_eq!(
+            msg.id().to_string(),
+            "%BUtTVIJyN5fUXzQy2uQfCCzlAg0s6laQQqFIu+kGnFM=.sha256"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_message_value() {
+        let invalid = [
+            r#"{"previous":null,"author":"@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519","sequence":-1,"timestamp":1,"hash":"sha256","content":{},"signature":""}"#,
+            r#"{"previous":null,"author":"","sequence":1,"timestamp":1,"hash":"sha256","content":{},"signature":""}"#,
+            r#"{"previous":null,"author":"@6ilZq3kN0F+dXFHAPjAwMm87JEb/VdB+LC9eIMW3sa0=.ed25519","sequence":1,"timestamp":1,"hash":"sha256","signature":""}"#,
+            r#"[]"#,
+        ];
+        for message in invalid.iter() {
+            assert!(Message::from_str(message).is_err());
+        }
+    }
 }
@@ -1,11 +1,13 @@
 mod base;
+mod content;
 mod encoding;
 mod error;
 mod message;
 mod privatebox;
 
 pub use base::Feed;
+pub use content::{Content, ContentRegistry, CustomContent};
 pub use encoding::{ssb_sha256, stringify_json};
 pub use error::{Error, Result};
-pub use message::Message;
+pub use message::{Message, MessageId, MessageValue};
 pub use privatebox::{is_privatebox, privatebox_cipher, privatebox_decipher};
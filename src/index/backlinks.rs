@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::Value;
+
+use crate::{crypto::ToSodiumObject, feed::Message};
+
+/// A cypherlink found in a message content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The linked feed, message or blob id.
+    pub link: String,
+    /// The keys of the objects containing the link, joined by dots,
+    /// e.g. `vote.link` or `mentions.link`. Array indexes are skipped.
+    pub path: String,
+}
+
+/// Check if `s` is a feed (`@`), message (`%`) or blob (`&`) id.
+pub fn is_cypherlink(s: &str) -> bool {
+    // blob links can carry a query, like the key of encrypted blobs
+    let s = s.split('?').next().unwrap_or_default();
+    match s.chars().next() {
+        Some('@') => s[1..].to_ed25519_pk().is_ok(),
+        Some('%') | Some('&') => s[1..].to_sha256().is_ok(),
+        _ => false,
+    }
+}
+
+/// Extract every cypherlink from a message content, at any depth.
+pub fn extract_links(content: &Value) -> Vec<Link> {
+    let mut links = Vec::new();
+    collect_links(content, &mut Vec::new(), &mut links);
+    links
+}
+
+fn collect_links<'a>(value: &'a Value, path: &mut Vec<&'a str>, links: &mut Vec<Link>) {
+    match value {
+        Value::String(s) if is_cypherlink(s) => links.push(Link {
+            link: s.split('?').next().unwrap_or_default().to_string(),
+            path: path.join("."),
+        }),
+        Value::Array(values) => {
+            for value in values {
+                collect_links(value, path, links);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                path.push(key);
+                collect_links(value, path, links);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A message linking to another message, feed or blob.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Backlink {
+    /// Id of the linking message.
+    pub source: String,
+    pub author: String,
+    /// `type` of the linking message.
+    pub xtype: Option<String>,
+    pub path: String,
+    pub timestamp: f64,
+}
+
+/// Index of the messages linking to each id.
+#[derive(Debug, Default)]
+pub struct BacklinksIndex {
+    applied: HashSet<String>,
+    backlinks: HashMap<String, Vec<Backlink>>,
+}
+
+impl BacklinksIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the links of a message to the index.
+    pub fn apply(&mut self, msg: &Message) {
+        let source = msg.id().to_string();
+        if !self.applied.insert(source.clone()) {
+            return;
+        }
+
+        let content = msg.content();
+        let xtype = content.get("type").and_then(Value::as_str);
+        for Link { link, path } in extract_links(content) {
+            let backlinks = self.backlinks.entry(link).or_default();
+            let backlink = Backlink {
+                source: source.clone(),
+                author: msg.author().clone(),
+                xtype: xtype.map(String::from),
+                path,
+                timestamp: msg.timestamp(),
+            };
+            let pos = backlinks.partition_point(|b| b.timestamp <= backlink.timestamp);
+            backlinks.insert(pos, backlink);
+        }
+    }
+
+    /// Messages linking to `target`, sorted by timestamp. Optionally only
+    /// the ones of type `xtype`, or linking it from `path` or any path
+    /// under it (e.g. `mentions` also matches `mentions.link`).
+    pub fn backlinks(
+        &self,
+        target: &str,
+        xtype: Option<&str>,
+        path: Option<&str>,
+    ) -> Vec<&Backlink> {
+        let path_matches = |backlink: &Backlink| match path {
+            Some(path) => {
+                backlink.path == path
+                    || (backlink.path.starts_with(path)
+                        && backlink.path[path.len()..].starts_with('.'))
+            }
+            None => true,
+        };
+        self.backlinks
+            .get(target)
+            .into_iter()
+            .flatten()
+            .filter(|backlink| xtype.is_none() || backlink.xtype.as_deref() == xtype)
+            .filter(|backlink| path_matches(backlink))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{feed::Result, keystore::OwnedIdentity};
+    use serde_json::json;
+
+    const MSG: &str = "%seUEAo7PTyA7vNwnOrmGIsUFfpyRzOvzGVv1QCb/Fz8=.sha256";
+    const FEED: &str = "@hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519";
+    const BLOB: &str = "&uaGieSQDJcHfUp6hjIcIq55GoZh4Ug7tNmgaohoxrpw=.sha256";
+
+    #[test]
+    fn test_extract_links() {
+        let content = json!({
+            "type": "post",
+            "text": "not a link @hxGxqPrplLjRG2vtjQL87abX4QKqeLgCwQpS730nNwE=.ed25519",
+            "root": MSG,
+            "mentions": [
+                FEED,
+                { "link": format!("{}?unbox=ZE2/qsGCMqxyoMFsl1GbjxJKAhV8S2IuaNgAC2hmsc8=.boxs", BLOB) },
+                { "link": "#kuska" },
+            ],
+            "nested": { "deep": { "link": "%invalid.sha256" } },
+        });
+        let link = |link: &str, path: &str| Link {
+            link: link.to_string(),
+            path: path.to_string(),
+        };
+        assert_eq!(
+            extract_links(&content),
+            vec![
+                link(MSG, "root"),
+                link(FEED, "mentions"),
+                link(BLOB, "mentions.link"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backlinks() -> Result<()> {
+        let (alice, bob) = (OwnedIdentity::create(), OwnedIdentity::create());
+        let mut index = BacklinksIndex::new();
+
+        let post = Message::sign(
+            None,
+            &alice,
+            json!({ "type": "post", "text": "hi", "root": MSG, "mentions": [{ "link": BLOB }] }),
+        )?;
+        let vote = Message::sign(
+            None,
+            &bob,
+            json!({ "type": "vote", "vote": { "link": MSG, "value": 1 } }),
+        )?;
+        for msg in [&post, &vote, &post] {
+            index.apply(msg);
+        }
+
+        assert_eq!(index.backlinks(MSG, None, None).len(), 2);
+        let votes = index.backlinks(MSG, Some("vote"), None);
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0].source, vote.id().to_string());
+        assert_eq!(votes[0].author, bob.id);
+        assert_eq!(votes[0].path, "vote.link");
+        assert_eq!(index.backlinks(MSG, None, Some("vote")).len(), 1);
+        assert_eq!(index.backlinks(MSG, None, Some("vo")).len(), 0);
+
+        let mentions = index.backlinks(BLOB, Some("post"), Some("mentions"));
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].source, post.id().to_string());
+        assert!(index.backlinks(FEED, None, None).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{HashMap, HashSet};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use super::{is_newer, Version};
+use crate::{
+    api::dto::content::TypedMessage,
+    feed::{Content, Message},
+};
+
+const MAX_CHANNEL_LEN: usize = 30;
+
+static HASHTAG_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r##"(?:^|[\s(\[])#([^\s,.?!<>()\[\]"#]+)"##).unwrap());
+
+/// Normalize a channel name like ssb-ref `normalizeChannel`: lowercase,
+/// without whitespace, punctuation or `#`, and at most 30 characters long.
+pub fn normalize_channel(channel: &str) -> Option<String> {
+    let channel: String = channel
+        .to_lowercase()
+        .chars()
+        .filter(|c| !c.is_whitespace() && !",.?!<>()[]\"#".contains(*c))
+        .take(MAX_CHANNEL_LEN)
+        .collect();
+    if channel.is_empty() {
+        None
+    } else {
+        Some(channel)
+    }
+}
+
+/// The normalized `#hashtags` of a text.
+pub fn hashtags(text: &str) -> Vec<String> {
+    let mut hashtags: Vec<_> = HASHTAG_REGEX
+        .captures_iter(text)
+        .filter_map(|cap| normalize_channel(&cap[1]))
+        .collect();
+    hashtags.sort_unstable();
+    hashtags.dedup();
+    hashtags
+}
+
+/// Index of the channel subscriptions of each feed, and of the posts in each
+/// channel, either by their `channel` field or by their hashtags.
+#[derive(Debug, Default)]
+pub struct ChannelIndex {
+    subscriptions: HashMap<String, HashMap<String, (Version, bool)>>,
+    timelines: HashMap<String, Vec<(f64, String)>>,
+    applied: HashSet<String>,
+}
+
+impl ChannelIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a `channel` or `post` message.
+    pub fn apply(&mut self, msg: &Message) {
+        match msg.typed_content() {
+            Content::Typed(TypedMessage::Channel {
+                channel,
+                subscribed,
+            }) => {
+                let channel = match normalize_channel(&channel) {
+                    Some(channel) => channel,
+                    None => return,
+                };
+                let version = (msg.timestamp(), msg.sequence());
+                let subscriptions = self.subscriptions.entry(msg.author().clone()).or_default();
+                let last = subscriptions.get(&channel).map(|(version, _)| version);
+                if is_newer(last, version) {
+                    subscriptions.insert(channel, (version, subscribed));
+                }
+            }
+            Content::Typed(TypedMessage::Post { text, channel, .. }) => {
+                let id = msg.id().to_string();
+                if !self.applied.insert(id.clone()) {
+                    return;
+                }
+                let mut channels = hashtags(&text);
+                channels.extend(channel.as_deref().and_then(normalize_channel));
+                channels.sort_unstable();
+                channels.dedup();
+                for channel in channels {
+                    let timeline = self.timelines.entry(channel).or_default();
+                    let pos = timeline.partition_point(|(ts, _)| *ts >= msg.timestamp());
+                    timeline.insert(pos, (msg.timestamp(), id.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Channels `feed` is currently subscribed to, sorted.
+    pub fn subscriptions(&self, feed: &str) -> Vec<&str> {
+        let mut channels: Vec<_> = self
+            .subscriptions
+            .get(feed)
+            .into_iter()
+            .flatten()
+            .filter(|(_, (_, subscribed))| *subscribed)
+            .map(|(channel, _)| channel.as_str())
+            .collect();
+        channels.sort_unstable();
+        channels
+    }
+
+    pub fn is_subscribed(&self, feed: &str, channel: &str) -> bool {
+        let channel = match normalize_channel(channel) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        let subscription = self
+            .subscriptions
+            .get(feed)
+            .and_then(|subscriptions| subscriptions.get(&channel));
+        matches!(subscription, Some((_, true)))
+    }
+
+    /// Ids of the posts in `channel`, newest first.
+    pub fn timeline(&self, channel: &str, limit: Option<usize>) -> Vec<&str> {
+        let timeline = normalize_channel(channel).and_then(|channel| self.timelines.get(&channel));
+        timeline
+            .into_iter()
+            .flatten()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(_, id)| id.as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{api::dto::content::Post, feed::Result, keystore::OwnedIdentity};
+    use serde_json::json;
+
+    #[test]
+    fn test_normalize_channel() {
+        assert_eq!(
+            normalize_channel("#Kuska SSB!").as_deref(),
+            Some("kuskassb")
+        );
+        assert_eq!(
+            normalize_channel(&"a".repeat(40)).as_deref(),
+            Some("a".repeat(30).as_str())
+        );
+        assert_eq!(normalize_channel("#?!"), None);
+        assert_eq!(
+            hashtags("#Rust and #kuska, (#rust) but not a#b"),
+            vec!["kuska", "rust"]
+        );
+    }
+
+    #[test]
+    fn test_channels() -> Result<()> {
+        let id = OwnedIdentity::create();
+        let channel = |prev, channel: &str, subscribed: bool, timestamp| {
+            let content =
+                json!({ "type": "channel", "channel": channel, "subscribed": subscribed });
+            Message::sign_at(prev, &id, content, timestamp)
+        };
+        let sub1 = channel(None, "Kuska", true, 1000)?;
+        let sub2 = channel(Some(&sub1), "rust", true, 1001)?;
+        let unsub = channel(Some(&sub2), "#kuska", false, 1002)?;
+
+        let p1 = Message::sign_at(
+            Some(&unsub),
+            &id,
+            Post::new("hello".to_string(), None)
+                .channel("KUSKA".to_string())
+                .to_msg()?,
+            1003,
+        )?;
+        let p2 = Message::sign_at(
+            Some(&p1),
+            &id,
+            Post::new("more on #kuska and #Rust".to_string(), None).to_msg()?,
+            1004,
+        )?;
+
+        let mut index = ChannelIndex::new();
+        for msg in [&unsub, &p2, &sub1, &sub2, &p1, &p2] {
+            index.apply(msg);
+        }
+
+        assert_eq!(index.subscriptions(&id.id), vec!["rust"]);
+        assert!(index.is_subscribed(&id.id, "#Rust"));
+        assert!(!index.is_subscribed(&id.id, "kuska"));
+
+        let (p1, p2) = (p1.id().to_string(), p2.id().to_string());
+        assert_eq!(
+            index.timeline("#kuska", None),
+            vec![p2.as_str(), p1.as_str()]
+        );
+        assert_eq!(index.timeline("Kuska", Some(1)), vec![p2.as_str()]);
+        assert_eq!(index.timeline("rust", None), vec![p2.as_str()]);
+        assert!(index.timeline("other", None).is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, VecDeque};
+
+use futures::channel::mpsc;
+
+use super::{is_newer, Version};
+use crate::{
+    api::dto::content::{FriendsHops, RelationshipQuery, TypedMessage},
+    feed::{Content, Message},
+};
+
+/// The relationship of a feed with another one, as set by its latest
+/// `contact` message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Following,
+    Blocking,
+    /// Unfollowed or unblocked.
+    Neutral,
+}
+
+impl Relation {
+    /// The edge value used by ssb-friends.
+    pub fn value(&self) -> i32 {
+        match self {
+            Relation::Following => 1,
+            Relation::Blocking => -1,
+            Relation::Neutral => -2,
+        }
+    }
+}
+
+/// A change in the graph caused by a new `contact` message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphChange {
+    pub source: String,
+    pub dest: String,
+    pub relation: Relation,
+}
+
+/// Social graph computed from `contact` messages.
+#[derive(Debug, Default)]
+pub struct FriendsGraph {
+    edges: HashMap<String, HashMap<String, (Version, Relation)>>,
+    subscribers: Vec<mpsc::UnboundedSender<GraphChange>>,
+}
+
+impl FriendsGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stream of the changes made by the messages applied from now on.
+    pub fn changes(&mut self) -> mpsc::UnboundedReceiver<GraphChange> {
+        let (sender, receiver) = mpsc::unbounded();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Apply a `contact` message, returns the change it made, if any.
+    pub fn apply(&mut self, msg: &Message) -> Option<GraphChange> {
+        let (dest, relation) = match msg.typed_content() {
+            Content::Typed(TypedMessage::Contact {
+                contact: Some(contact),
+                blocking,
+                following,
+                ..
+            }) if contact.starts_with('@') => match (blocking, following) {
+                (Some(true), _) => (contact, Relation::Blocking),
+                (_, Some(true)) => (contact, Relation::Following),
+                (Some(false), _) | (_, Some(false)) => (contact, Relation::Neutral),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let version = (msg.timestamp(), msg.sequence());
+        let edges = self.edges.entry(msg.author().clone()).or_default();
+        let last = edges.get(&dest);
+        if !is_newer(last.map(|(version, _)| version), version) {
+            return None;
+        }
+        let changed = last.map(|(_, last)| *last) != Some(relation);
+        edges.insert(dest.clone(), (version, relation));
+        if !changed {
+            return None;
+        }
+
+        let change = GraphChange {
+            source: msg.author().clone(),
+            dest,
+            relation,
+        };
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(change.clone()).is_ok());
+        Some(change)
+    }
+
+    /// The relationship of `source` with `dest`, if any.
+    pub fn relation(&self, source: &str, dest: &str) -> Option<Relation> {
+        self.edges
+            .get(source)
+            .and_then(|edges| edges.get(dest))
+            .map(|(_, relation)| *relation)
+    }
+
+    pub fn is_following(&self, query: &RelationshipQuery) -> bool {
+        self.relation(&query.source, &query.dest) == Some(Relation::Following)
+    }
+
+    pub fn is_blocking(&self, query: &RelationshipQuery) -> bool {
+        self.relation(&query.source, &query.dest) == Some(Relation::Blocking)
+    }
+
+    /// Feeds blocked by `source`.
+    pub fn blocks(&self, source: &str) -> Vec<&str> {
+        let mut blocks: Vec<_> = self
+            .edges
+            .get(source)
+            .into_iter()
+            .flatten()
+            .filter(|(_, (_, relation))| *relation == Relation::Blocking)
+            .map(|(dest, _)| dest.as_str())
+            .collect();
+        blocks.sort_unstable();
+        blocks
+    }
+
+    /// Feeds followed by `feed`, or following `feed` if `reverse`.
+    fn neighbours<'a>(&'a self, feed: &str, reverse: bool) -> Vec<(&'a str, Relation)> {
+        if reverse {
+            self.edges
+                .iter()
+                .filter_map(|(source, edges)| {
+                    edges
+                        .get(feed)
+                        .map(|(_, relation)| (source.as_str(), *relation))
+                })
+                .collect()
+        } else {
+            self.edges
+                .get(feed)
+                .into_iter()
+                .flatten()
+                .map(|(dest, (_, relation))| (dest.as_str(), *relation))
+                .collect()
+        }
+    }
+
+    /// Hops distance of the feeds reachable from `start` following at most
+    /// `max` hops, like ssb-friends. Feeds blocked by `start` have a distance
+    /// of `-1`. With `reverse` the distances are computed looking "in" to
+    /// `start`, following the feeds that follow it.
+    pub fn hops(&self, start: &str, max: i32, reverse: bool) -> HashMap<String, i32> {
+        let mut hops = HashMap::new();
+        hops.insert(start.to_string(), 0);
+
+        for (feed, relation) in self.neighbours(start, reverse) {
+            if relation == Relation::Blocking && feed != start {
+                hops.insert(feed.to_string(), -1);
+            }
+        }
+
+        let mut queue = VecDeque::from(vec![(start, 0)]);
+        while let Some((feed, distance)) = queue.pop_front() {
+            if distance >= max {
+                continue;
+            }
+            for (next, relation) in self.neighbours(feed, reverse) {
+                if relation == Relation::Following && !hops.contains_key(next) {
+                    hops.insert(next.to_string(), distance + 1);
+                    queue.push_back((next, distance + 1));
+                }
+            }
+        }
+
+        hops
+    }
+
+    /// Answer a `friends.hops` request, `own` is used if the request has no
+    /// `start`.
+    pub fn hops_query(&self, own: &str, query: &FriendsHops) -> HashMap<String, i32> {
+        let start = query.start.as_deref().unwrap_or(own);
+        self.hops(start, query.max, query.reverse.unwrap_or(false))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{feed::Result, keystore::OwnedIdentity};
+    use futures::StreamExt;
+    use serde_json::json;
+
+    fn contact(
+        prev: Option<&Message>,
+        id: &OwnedIdentity,
+        dest: &OwnedIdentity,
+        field: &str,
+        value: bool,
+    ) -> Result<Message> {
+        let content = json!({ "type": "contact", "contact": dest.id, field: value });
+        Message::sign(prev, id, content)
+    }
+
+    fn query(source: &OwnedIdentity, dest: &OwnedIdentity) -> RelationshipQuery {
+        RelationshipQuery {
+            source: source.id.clone(),
+            dest: dest.id.clone(),
+        }
+    }
+
+    #[test]
+    fn test_friends_graph() -> Result<()> {
+        let ids: Vec<_> = (0..5).map(|_| OwnedIdentity::create()).collect();
+        let (a, b, c, d, e) = (&ids[0], &ids[1], &ids[2], &ids[3], &ids[4]);
+
+        let mut graph = FriendsGraph::new();
+        let changes = graph.changes();
+
+        let a1 = contact(None, a, b, "following", true)?;
+        let a2 = contact(Some(&a1), a, e, "blocking", true)?;
+        let b1 = contact(None, b, c, "following", true)?;
+        let b2 = contact(Some(&b1), b, e, "following", true)?;
+        let c1 = contact(None, c, d, "following", true)?;
+        for msg in [&a1, &a2, &b1, &b2, &c1] {
+            assert!(graph.apply(msg).is_some());
+        }
+        assert!(graph.apply(&a1).is_none());
+
+        let hops = graph.hops(&a.id, 2, false);
+        assert_eq!(hops.len(), 4);
+        assert_eq!(hops[&a.id], 0);
+        assert_eq!(hops[&b.id], 1);
+        assert_eq!(hops[&c.id], 2);
+        assert_eq!(hops[&e.id], -1);
+
+        let hops = graph.hops_query(
+            &c.id,
+            &FriendsHops {
+                max: 3,
+                reverse: Some(true),
+                start: None,
+            },
+        );
+        assert_eq!(hops.len(), 3);
+        assert_eq!(hops[&b.id], 1);
+        assert_eq!(hops[&a.id], 2);
+
+        assert!(graph.is_following(&query(a, b)));
+        assert!(graph.is_blocking(&query(a, e)));
+        assert!(!graph.is_following(&query(b, a)));
+        assert_eq!(graph.blocks(&a.id), vec![e.id.as_str()]);
+
+        let b3 = contact(Some(&b2), b, c, "following", false)?;
+        graph.apply(&b3);
+        assert_eq!(graph.relation(&b.id, &c.id), Some(Relation::Neutral));
+        assert!(!graph.hops(&a.id, 3, false).contains_key(&c.id));
+
+        drop(graph);
+        let received: Vec<_> = async_std::task::block_on(changes.collect());
+        assert_eq!(received.len(), 6);
+        assert_eq!(
+            received[5],
+            GraphChange {
+                source: b.id.clone(),
+                dest: c.id.clone(),
+                relation: Relation::Neutral,
+            }
+        );
+        Ok(())
+    }
+}
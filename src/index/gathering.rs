@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{is_newer, Version};
+use crate::{
+    api::dto::content::{Attendee, DateTime, Image, TypedMessage},
+    feed::{Content, Message},
+};
+
+const TITLE: &str = "title";
+const DESCRIPTION: &str = "description";
+const LOCATION: &str = "location";
+const START_DATETIME: &str = "startDateTime";
+const IMAGE: &str = "image";
+
+/// The current state of a gathering, computed by folding the `about`
+/// messages that refer to it.
+///
+/// The details of the gathering can only be changed by its author, while any
+/// feed can attend or leave. The latest update by timestamp wins, so
+/// messages can be applied in any order.
+#[derive(Debug, Clone)]
+pub struct GatheringState {
+    pub id: String,
+    pub author: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub start_datetime: Option<DateTime>,
+    pub image: Option<Image>,
+    updated: HashMap<&'static str, Version>,
+    attendees: BTreeMap<String, (Version, bool)>,
+}
+
+impl GatheringState {
+    /// Create the state of the gathering published in `msg`, returns `None`
+    /// if it is not a `gathering` message.
+    pub fn from_message(msg: &Message) -> Option<Self> {
+        match msg.typed_content() {
+            Content::Typed(TypedMessage::Gathering { .. }) => Some(GatheringState {
+                id: msg.id().to_string(),
+                author: msg.author().clone(),
+                title: None,
+                description: None,
+                location: None,
+                start_datetime: None,
+                image: None,
+                updated: HashMap::new(),
+                attendees: BTreeMap::new(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Apply an `about` message, returns if it refers to this gathering.
+    pub fn apply(&mut self, msg: &Message) -> bool {
+        let (title, description, location, start_datetime, image, attendee) =
+            match msg.typed_content() {
+                Content::Typed(TypedMessage::About {
+                    about,
+                    title,
+                    description,
+                    location,
+                    start_datetime,
+                    image,
+                    attendee,
+                    ..
+                }) if about == self.id => (
+                    title,
+                    description,
+                    location,
+                    start_datetime,
+                    image,
+                    attendee,
+                ),
+                _ => return false,
+            };
+
+        let version = (msg.timestamp(), msg.sequence());
+        if let Some(attendee) = attendee {
+            self.apply_attendee(msg.author(), attendee, version);
+        }
+
+        if *msg.author() == self.author {
+            Self::update(&mut self.updated, TITLE, &mut self.title, title, version);
+            Self::update(
+                &mut self.updated,
+                DESCRIPTION,
+                &mut self.description,
+                description,
+                version,
+            );
+            Self::update(
+                &mut self.updated,
+                LOCATION,
+                &mut self.location,
+                location,
+                version,
+            );
+            Self::update(
+                &mut self.updated,
+                START_DATETIME,
+                &mut self.start_datetime,
+                start_datetime,
+                version,
+            );
+            Self::update(&mut self.updated, IMAGE, &mut self.image, image, version);
+        }
+
+        true
+    }
+
+    fn update<T>(
+        updated: &mut HashMap<&'static str, Version>,
+        field: &'static str,
+        current: &mut Option<T>,
+        value: Option<T>,
+        version: Version,
+    ) {
+        if let Some(value) = value {
+            if is_newer(updated.get(field), version) {
+                updated.insert(field, version);
+                *current = Some(value);
+            }
+        }
+    }
+
+    fn apply_attendee(&mut self, author: &str, attendee: Attendee, version: Version) {
+        // a feed can only change its own attendance
+        if attendee.link != author {
+            return;
+        }
+        let last = self.attendees.get(author).map(|(last, _)| last);
+        if is_newer(last, version) {
+            self.attendees
+                .insert(attendee.link.clone(), (version, attendee.is_attending()));
+        }
+    }
+
+    /// Feeds currently attending the gathering.
+    pub fn attendees(&self) -> Vec<&str> {
+        self.attendees
+            .iter()
+            .filter(|(_, (_, attending))| *attending)
+            .map(|(feed, _)| feed.as_str())
+            .collect()
+    }
+
+    /// Feeds that attended the gathering and then declined.
+    pub fn not_attending(&self) -> Vec<&str> {
+        self.attendees
+            .iter()
+            .filter(|(_, (_, attending))| !*attending)
+            .map(|(feed, _)| feed.as_str())
+            .collect()
+    }
+
+    pub fn is_attending(&self, feed: &str) -> bool {
+        matches!(self.attendees.get(feed), Some((_, true)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::dto::content::{About, Gathering, Post},
+        feed::Result,
+        keystore::OwnedIdentity,
+    };
+
+    #[test]
+    fn test_gathering_state() -> Result<()> {
+        let (host, guest) = (OwnedIdentity::create(), OwnedIdentity::create());
+
+        let gathering = Message::sign(None, &host, Gathering::new().to_msg()?)?;
+        let mut state = GatheringState::from_message(&gathering).unwrap();
+        let id = gathering.id().to_string();
+
+        let details = Message::sign(
+            Some(&gathering),
+            &host,
+            About::new(id.clone())
+                .title("kuska meetup".to_string())
+                .location("barcelona".to_string())
+                .start_datetime(DateTime::new(1600000000000, "Europe/Madrid".to_string()))
+                .attendee(Attendee::new(host.id.clone(), true))
+                .to_msg()?,
+        )?;
+        assert!(state.apply(&details));
+
+        let join = Message::sign(
+            None,
+            &guest,
+            About::new(id.clone())
+                .title("hijacked".to_string())
+                .attendee(Attendee::new(guest.id.clone(), true))
+                .to_msg()?,
+        )?;
+        assert!(state.apply(&join));
+
+        let mut attendees = vec![host.id.as_str(), guest.id.as_str()];
+        attendees.sort_unstable();
+        assert_eq!(state.title.as_deref(), Some("kuska meetup"));
+        assert_eq!(state.location.as_deref(), Some("barcelona"));
+        assert_eq!(state.start_datetime.as_ref().unwrap().epoch, 1600000000000);
+        assert_eq!(state.attendees(), attendees);
+
+        let leave = Message::sign(
+            Some(&join),
+            &guest,
+            About::new(id.clone())
+                .attendee(Attendee::new(guest.id.clone(), false))
+                .to_msg()?,
+        )?;
+        let retitle = Message::sign(
+            Some(&details),
+            &host,
+            About::new(id)
+                .title("kuska meetup #2".to_string())
+                .to_msg()?,
+        )?;
+
+        // older updates applied later do not win
+        for msg in [&leave, &retitle, &details, &join] {
+            assert!(state.apply(msg));
+        }
+        assert_eq!(state.title.as_deref(), Some("kuska meetup #2"));
+        assert_eq!(state.attendees(), vec![host.id.as_str()]);
+        assert_eq!(state.not_attending(), vec![guest.id.as_str()]);
+        assert!(!state.is_attending(&guest.id));
+
+        let post = Message::sign(None, &guest, Post::new("hi".to_string(), None).to_msg()?)?;
+        assert!(!state.apply(&post));
+        assert!(GatheringState::from_message(&post).is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+//! Local indexes and reducers computed from feed messages.
+
+mod backlinks;
+mod channels;
+mod error;
+mod friends;
+mod gathering;
+mod profiles;
+mod query;
+mod tangles;
+mod votes;
+
+pub use backlinks::{extract_links, is_cypherlink, Backlink, BacklinksIndex, Link};
+pub use channels::{hashtags, normalize_channel, ChannelIndex};
+pub use error::{Error, Result};
+pub use friends::{FriendsGraph, GraphChange, Relation};
+pub use gathering::GatheringState;
+pub use profiles::{Profile, ProfileIndex};
+pub use query::{get_subset, query_matches};
+pub use tangles::{TangleIndex, TangleLinks, THREAD_TANGLE};
+pub use votes::{VoteIndex, VoteState, VoteSummary, DEFAULT_EXPRESSION};
+
+// updates are ordered by their timestamp, and by sequence for messages of
+// the same feed with the same timestamp.
+pub(crate) type Version = (f64, u64);
+
+pub(crate) fn is_newer(last: Option<&Version>, version: Version) -> bool {
+    match last {
+        Some(last) => version > *last,
+        None => true,
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use super::{is_newer, Version};
+use crate::{
+    api::dto::content::{Image, TypedMessage},
+    feed::{Content, Message},
+};
+
+#[derive(Debug, Clone)]
+struct Field<T> {
+    version: Version,
+    value: T,
+}
+
+fn update<T>(field: &mut Option<Field<T>>, value: Option<T>, version: Version) -> bool {
+    match value {
+        Some(value) if is_newer(field.as_ref().map(|field| &field.version), version) => {
+            *field = Some(Field { version, value });
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The current about values of a feed.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    name: Option<Field<String>>,
+    image: Option<Field<Image>>,
+    description: Option<Field<String>>,
+    public_web_hosting: Option<Field<bool>>,
+}
+
+impl Profile {
+    pub fn name(&self) -> Option<&String> {
+        self.name.as_ref().map(|field| &field.value)
+    }
+    pub fn image(&self) -> Option<&Image> {
+        self.image.as_ref().map(|field| &field.value)
+    }
+    pub fn description(&self) -> Option<&String> {
+        self.description.as_ref().map(|field| &field.value)
+    }
+    pub fn public_web_hosting(&self) -> Option<bool> {
+        self.public_web_hosting.as_ref().map(|field| field.value)
+    }
+}
+
+/// Profiles of feeds computed from `about` messages.
+///
+/// Abouts can be assigned by the feed itself or by its peers, for each field
+/// the latest value by timestamp wins. `publicWebHosting` is only taken from
+/// the feed itself.
+#[derive(Debug, Default)]
+pub struct ProfileIndex {
+    self_assigned_only: bool,
+    profiles: HashMap<String, Profile>,
+}
+
+impl ProfileIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore the abouts that a feed did not publish about itself.
+    pub fn self_assigned_only(self, self_assigned_only: bool) -> Self {
+        Self {
+            self_assigned_only,
+            ..self
+        }
+    }
+
+    /// Apply an `about` message, returns if it changed any profile.
+    pub fn apply(&mut self, msg: &Message) -> bool {
+        let (about, name, image, description, public_web_hosting) = match msg.typed_content() {
+            Content::Typed(TypedMessage::About {
+                about,
+                name,
+                image,
+                description,
+                public_web_hosting,
+                ..
+            }) if about.starts_with('@') => (about, name, image, description, public_web_hosting),
+            _ => return false,
+        };
+
+        if self.self_assigned_only && about != *msg.author() {
+            return false;
+        }
+
+        let version = (msg.timestamp(), msg.sequence());
+        let profile = self.profiles.entry(about.clone()).or_default();
+
+        let name = update(&mut profile.name, name, version);
+        let image = update(&mut profile.image, image, version);
+        let description = update(&mut profile.description, description, version);
+        // only the owner can opt in to public web hosting
+        let public_web_hosting = about == *msg.author()
+            && update(&mut profile.public_web_hosting, public_web_hosting, version);
+
+        name || image || description || public_web_hosting
+    }
+
+    pub fn get(&self, feed: &str) -> Option<&Profile> {
+        self.profiles.get(feed)
+    }
+
+    pub fn name(&self, feed: &str) -> Option<&String> {
+        self.get(feed).and_then(Profile::name)
+    }
+
+    pub fn image(&self, feed: &str) -> Option<&Image> {
+        self.get(feed).and_then(Profile::image)
+    }
+
+    pub fn description(&self, feed: &str) -> Option<&String> {
+        self.get(feed).and_then(Profile::description)
+    }
+
+    pub fn public_web_hosting(&self, feed: &str) -> Option<bool> {
+        self.get(feed).and_then(Profile::public_web_hosting)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{api::dto::content::About, feed::Result, keystore::OwnedIdentity};
+
+    #[test]
+    fn test_profiles() -> Result<()> {
+        let (alice, bob) = (OwnedIdentity::create(), OwnedIdentity::create());
+
+        let own = Message::sign_at(
+            None,
+            &alice,
+            About::new(alice.id.clone())
+                .name("alice".to_string())
+                .image(Image::OnlyLink(
+                    "&uaGieSQDJcHfUp6hjIcIq55GoZh4Ug7tNmgaohoxrpw=.sha256".to_string(),
+                ))
+                .public_web_hosting(false)
+                .to_msg()?,
+            1000,
+        )?;
+        let renamed = Message::sign_at(
+            Some(&own),
+            &alice,
+            About::new(alice.id.clone())
+                .name("alice!".to_string())
+                .to_msg()?,
+            1001,
+        )?;
+        let by_peer = Message::sign_at(
+            None,
+            &bob,
+            About::new(alice.id.clone())
+                .name("ally".to_string())
+                .description("a friend".to_string())
+                .public_web_hosting(true)
+                .to_msg()?,
+            1002,
+        )?;
+
+        let mut index = ProfileIndex::new();
+        let mut self_only = ProfileIndex::new().self_assigned_only(true);
+        for msg in [&renamed, &own, &by_peer] {
+            index.apply(msg);
+            self_only.apply(msg);
+        }
+
+        // the peer-assigned name is the latest one
+        assert_eq!(index.name(&alice.id).unwrap(), "ally");
+        assert_eq!(index.description(&alice.id).unwrap(), "a friend");
+        assert_eq!(
+            index.image(&alice.id).unwrap().link(),
+            "&uaGieSQDJcHfUp6hjIcIq55GoZh4Ug7tNmgaohoxrpw=.sha256"
+        );
+        // peers cannot opt alice in to public web hosting
+        assert_eq!(index.public_web_hosting(&alice.id), Some(false));
+
+        assert_eq!(self_only.name(&alice.id).unwrap(), "alice!");
+        assert_eq!(self_only.description(&alice.id), None);
+
+        // already applied
+        assert!(!index.apply(&own));
+        assert!(index.get(&bob.id).is_none());
+        Ok(())
+    }
+}
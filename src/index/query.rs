@@ -0,0 +1,161 @@
+use serde_json::{json, Value};
+
+use super::error::{Error, Result};
+use crate::{
+    api::dto::content::{SubsetQuery, SubsetQueryOptions},
+    feed::Message,
+};
+
+/// Check if a message matches an ssb-ql-1 query.
+///
+/// Since `and` and `or` queries have the same shape, the operation is
+/// always taken from the `op` field.
+pub fn query_matches(query: &SubsetQuery, msg: &Message) -> Result<bool> {
+    match query {
+        SubsetQuery::Type { op, string } if op == "type" => {
+            Ok(msg.content().get("type").and_then(Value::as_str) == Some(string.as_str()))
+        }
+        SubsetQuery::Author { op, feed } if op == "author" => Ok(msg.author() == feed),
+        SubsetQuery::And { op, args } | SubsetQuery::Or { op, args } => {
+            if args.is_empty() {
+                return Err(Error::InvalidQuery(format!("{} without args", op)));
+            }
+            match op.as_str() {
+                "and" => {
+                    for arg in args {
+                        if !query_matches(arg, msg)? {
+                            return Ok(false);
+                        }
+                    }
+                    Ok(true)
+                }
+                "or" => {
+                    for arg in args {
+                        if query_matches(arg, msg)? {
+                            return Ok(true);
+                        }
+                    }
+                    Ok(false)
+                }
+                op => Err(Error::InvalidQuery(format!("unknown op {}", op))),
+            }
+        }
+        SubsetQuery::Type { op, .. } | SubsetQuery::Author { op, .. } => {
+            Err(Error::InvalidQuery(format!("unexpected op {}", op)))
+        }
+    }
+}
+
+/// Evaluate a `partialReplication.getSubset` query, returning the data
+/// events sorted by timestamp.
+///
+/// By default results are ascending, include keys and are not limited.
+pub fn get_subset<'a, I>(
+    messages: I,
+    query: &SubsetQuery,
+    opts: Option<&SubsetQueryOptions>,
+) -> Result<Vec<Value>>
+where
+    I: IntoIterator<Item = &'a Message>,
+{
+    let descending = opts.and_then(|opts| opts.descending).unwrap_or(false);
+    let keys = opts.and_then(|opts| opts.keys).unwrap_or(true);
+    let page_limit = opts
+        .and_then(|opts| opts.page_limit)
+        .map_or(usize::MAX, |limit| limit as usize);
+
+    let mut matched = Vec::new();
+    for msg in messages {
+        if query_matches(query, msg)? {
+            matched.push(msg);
+        }
+    }
+    matched.sort_by(|a, b| {
+        a.timestamp()
+            .total_cmp(&b.timestamp())
+            .then_with(|| a.author().cmp(b.author()))
+            .then_with(|| a.sequence().cmp(&b.sequence()))
+    });
+    if descending {
+        matched.reverse();
+    }
+
+    Ok(matched
+        .into_iter()
+        .take(page_limit)
+        .map(|msg| {
+            if keys {
+                json!({ "key": msg.id().to_string(), "value": msg.value().as_json() })
+            } else {
+                msg.value().as_json().clone()
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{api::dto::content::Post, keystore::OwnedIdentity};
+
+    #[test]
+    fn test_get_subset() -> Result<()> {
+        let (alice, bob) = (OwnedIdentity::create(), OwnedIdentity::create());
+        let post = |prev, id, text: &str| {
+            Message::sign(
+                prev,
+                id,
+                Post::new(text.to_string(), None).to_msg().unwrap(),
+            )
+            .unwrap()
+        };
+        let a1 = post(None, &alice, "a1");
+        let a2 = Message::sign(
+            Some(&a1),
+            &alice,
+            json!({ "type": "vote", "vote": { "link": a1.id().to_string(), "value": 1 } }),
+        )
+        .unwrap();
+        let a3 = post(Some(&a2), &alice, "a3");
+        let b1 = post(None, &bob, "b1");
+        let messages = vec![b1.clone(), a3.clone(), a2, a1.clone()];
+
+        let query: SubsetQuery = serde_json::from_value(json!({
+            "op": "and",
+            "args": [
+                { "op": "type", "string": "post" },
+                { "op": "or", "args": [{ "op": "author", "feed": alice.id }] },
+            ],
+        }))?;
+        let found = get_subset(&messages, &query, None)?;
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0]["key"], a1.id().to_string());
+        assert_eq!(found[1]["key"], a3.id().to_string());
+
+        let opts = SubsetQueryOptions {
+            descending: Some(true),
+            keys: Some(false),
+            page_limit: Some(1),
+        };
+        let found = get_subset(&messages, &query, Some(&opts))?;
+        assert_eq!(found, vec![a3.value().as_json().clone()]);
+
+        let query: SubsetQuery = serde_json::from_value(json!({
+            "op": "or",
+            "args": [
+                { "op": "author", "feed": bob.id },
+                { "op": "type", "string": "vote" },
+            ],
+        }))?;
+        assert_eq!(get_subset(&messages, &query, None)?.len(), 2);
+        assert!(query_matches(&query, &b1)?);
+        assert!(!query_matches(&query, &a1)?);
+
+        let invalid: SubsetQuery = serde_json::from_value(json!({ "op": "xor", "args": [] }))?;
+        assert!(matches!(
+            get_subset(&messages, &invalid, None),
+            Err(Error::InvalidQuery(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, HashSet};
+
+use serde_json::{json, Value};
+
+use crate::{
+    api::dto::{content::Branch, TanglesThread},
+    crypto::ed25519,
+    feed::{is_privatebox, privatebox_decipher, Message},
+};
+
+/// Name of the tangle of post threads. Posts without a `tangles.thread`
+/// field are part of it through their `root` and `branch` fields.
+pub const THREAD_TANGLE: &str = "thread";
+
+/// The `tangles.<name>` field of a message content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TangleLinks {
+    pub root: String,
+    pub previous: Option<Vec<String>>,
+}
+
+impl TangleLinks {
+    /// Get the links of the tangle `name` from a message content.
+    pub fn from_content(content: &Value, name: &str) -> Option<Self> {
+        if let Some(tangle) = content.get("tangles").and_then(|tangles| tangles.get(name)) {
+            return serde_json::from_value(tangle.clone()).ok();
+        }
+        if name != THREAD_TANGLE {
+            return None;
+        }
+        let root = content.get("root").and_then(Value::as_str)?;
+        let previous = content
+            .get("branch")
+            .and_then(|branch| serde_json::from_value::<Branch>(branch.clone()).ok())
+            .map(|branch| branch.links().into_iter().map(String::from).collect());
+        Some(TangleLinks {
+            root: root.to_string(),
+            previous,
+        })
+    }
+
+    fn tangle_names(content: &Value) -> Vec<String> {
+        let mut names: Vec<_> = content
+            .get("tangles")
+            .and_then(Value::as_object)
+            .map(|tangles| tangles.keys().cloned().collect())
+            .unwrap_or_default();
+        if !names.iter().any(|name| name == THREAD_TANGLE) && content.get("root").is_some() {
+            names.push(THREAD_TANGLE.to_string());
+        }
+        names
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    timestamp: f64,
+    private: bool,
+}
+
+#[derive(Debug, Default)]
+struct Tangle {
+    previous: HashMap<String, Vec<String>>,
+}
+
+/// Index of the tangles the applied messages are part of.
+#[derive(Debug, Default)]
+pub struct TangleIndex {
+    sk: Option<ed25519::SecretKey>,
+    messages: HashMap<String, Entry>,
+    tangles: HashMap<(String, String), Tangle>,
+}
+
+impl TangleIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decrypt private messages addressed to the owner of `sk`.
+    pub fn decrypt_with(self, sk: ed25519::SecretKey) -> Self {
+        Self {
+            sk: Some(sk),
+            ..self
+        }
+    }
+
+    fn decrypt(&self, content: &Value) -> Option<Value> {
+        let text = content.as_str().filter(|text| is_privatebox(text))?;
+        let plaintext = privatebox_decipher(text, self.sk.as_ref()?).ok()??;
+        serde_json::from_str(&plaintext).ok()
+    }
+
+    /// Add a message to the index.
+    pub fn apply(&mut self, msg: &Message) {
+        let id = msg.id().to_string();
+        let mut value = msg.value().as_json().clone();
+
+        let private = match self.decrypt(msg.content()) {
+            Some(content) => {
+                value["content"] = content;
+                value["private"] = Value::Bool(true);
+                true
+            }
+            None => false,
+        };
+
+        for name in TangleLinks::tangle_names(&value["content"]) {
+            if let Some(links) = TangleLinks::from_content(&value["content"], &name) {
+                self.tangles
+                    .entry((name, links.root))
+                    .or_default()
+                    .previous
+                    .insert(id.clone(), links.previous.unwrap_or_default());
+            }
+        }
+
+        self.messages.insert(
+            id,
+            Entry {
+                value,
+                timestamp: msg.timestamp(),
+                private,
+            },
+        );
+    }
+
+    /// Ids of the messages in the tangle, starting with the root, sorted so
+    /// that each message goes after the ones it links as `previous`.
+    /// Concurrent messages are sorted by timestamp.
+    pub fn sorted(&self, name: &str, root: &str) -> Vec<String> {
+        let empty = HashMap::new();
+        let previous = self
+            .tangles
+            .get(&(name.to_string(), root.to_string()))
+            .map_or(&empty, |tangle| &tangle.previous);
+
+        let mut pending: Vec<&str> = previous
+            .keys()
+            .map(String::as_str)
+            .filter(|id| *id != root)
+            .collect();
+        let timestamp = |id: &str| self.messages.get(id).map_or(0.0, |entry| entry.timestamp);
+        pending.sort_by(|a, b| timestamp(a).total_cmp(&timestamp(b)).then(a.cmp(b)));
+
+        let members: HashSet<&str> = pending.iter().copied().collect();
+        let mut sorted = Vec::with_capacity(pending.len() + 1);
+        let mut done = HashSet::new();
+        if self.messages.contains_key(root) {
+            sorted.push(root);
+        }
+        while !pending.is_empty() {
+            let ready =
+                pending.iter().position(|id| {
+                    previous.get(*id).into_iter().flatten().all(|prev| {
+                        !members.contains(prev.as_str()) || done.contains(prev.as_str())
+                    })
+                });
+            // on cycles, continue with the oldest message
+            let id = pending.remove(ready.unwrap_or(0));
+            done.insert(id);
+            sorted.push(id);
+        }
+        sorted.into_iter().map(String::from).collect()
+    }
+
+    /// Messages of the tangle that are not linked as `previous` by others,
+    /// to be used as `previous` of a new message.
+    pub fn heads(&self, name: &str, root: &str) -> Vec<String> {
+        let previous = match self.tangles.get(&(name.to_string(), root.to_string())) {
+            Some(tangle) => &tangle.previous,
+            None => return vec![root.to_string()],
+        };
+        let linked: HashSet<&str> = previous.values().flatten().map(String::as_str).collect();
+        let mut heads: Vec<_> = previous
+            .keys()
+            .map(String::as_str)
+            .chain(std::iter::once(root))
+            .filter(|id| !linked.contains(id))
+            .collect();
+        heads.sort_unstable();
+        heads.dedup();
+        heads.into_iter().map(String::from).collect()
+    }
+
+    /// The `tangles.<name>` field of a new message of the tangle.
+    pub fn new_links(&self, name: &str, root: &str) -> TangleLinks {
+        TangleLinks {
+            root: root.to_string(),
+            previous: Some(self.heads(name, root)),
+        }
+    }
+
+    /// Answer a `tangles.thread` request, returning the data events in order.
+    pub fn thread(&self, args: &TanglesThread) -> Vec<Value> {
+        let keys = args.keys.unwrap_or(false);
+        let values = args.values.unwrap_or(true);
+        let private = args.private.unwrap_or(false);
+        let limit = match args.limit {
+            Some(limit) if limit >= 0 => limit as usize,
+            _ => usize::MAX,
+        };
+
+        self.sorted(THREAD_TANGLE, &args.root)
+            .into_iter()
+            .filter_map(|id| {
+                let entry = self.messages.get(&id)?;
+                Some((id, entry))
+            })
+            .filter(|(_, entry)| private || !entry.private)
+            .take(limit)
+            .map(|(id, entry)| match (keys, values) {
+                (true, false) => Value::String(id),
+                (false, true) => entry.value.clone(),
+                _ => json!({ "key": id, "value": entry.value }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::dto::content::Post,
+        feed::{privatebox_cipher, Result},
+        keystore::OwnedIdentity,
+    };
+
+    #[test]
+    fn test_tangle_links() {
+        let content = json!({
+            "type": "post",
+            "root": "%a",
+            "branch": ["%b", "%c"],
+            "tangles": { "gathering": { "root": "%g", "previous": null } },
+        });
+        assert_eq!(
+            TangleLinks::from_content(&content, THREAD_TANGLE),
+            Some(TangleLinks {
+                root: "%a".to_string(),
+                previous: Some(vec!["%b".to_string(), "%c".to_string()]),
+            })
+        );
+        assert_eq!(
+            TangleLinks::from_content(&content, "gathering")
+                .unwrap()
+                .root,
+            "%g"
+        );
+        assert_eq!(TangleLinks::from_content(&content, "other"), None);
+    }
+
+    #[test]
+    fn test_thread() -> Result<()> {
+        let (alice, bob) = (OwnedIdentity::create(), OwnedIdentity::create());
+        let mut index = TangleIndex::new().decrypt_with(alice.sk.clone());
+
+        let root = Message::sign(None, &alice, Post::new("root".to_string(), None).to_msg()?)?;
+        let root_id = root.id().to_string();
+        let reply = |prev, id: &OwnedIdentity, text: &str, previous: Vec<String>| {
+            let content = json!({
+                "type": "post",
+                "text": text,
+                "tangles": { "thread": { "root": root_id, "previous": previous } },
+            });
+            Message::sign(prev, id, content)
+        };
+
+        let a = reply(Some(&root), &alice, "a", vec![root_id.clone()])?;
+        let b = reply(None, &bob, "b", vec![root_id.clone()])?;
+        let c = reply(
+            Some(&a),
+            &alice,
+            "c",
+            vec![a.id().to_string(), b.id().to_string()],
+        )?;
+
+        let private = json!({
+            "type": "post",
+            "text": "secret",
+            "root": root_id,
+            "branch": c.id().to_string(),
+        });
+        let private = privatebox_cipher(&private.to_string(), &[&alice.id])?;
+        let d = Message::sign(Some(&c), &alice, Value::String(private))?;
+
+        for msg in [&d, &c, &b, &a, &root] {
+            index.apply(msg);
+        }
+
+        let sorted = index.sorted(THREAD_TANGLE, &root_id);
+        assert_eq!(sorted.len(), 5);
+        assert_eq!(sorted[0], root_id);
+        assert_eq!(sorted[3], c.id().to_string());
+
+        let heads = vec![d.id().to_string()];
+        assert_eq!(index.heads(THREAD_TANGLE, &root_id), heads);
+        assert_eq!(
+            index.new_links(THREAD_TANGLE, &root_id).previous,
+            Some(heads)
+        );
+
+        let public = index.thread(&TanglesThread::new(root_id.clone()));
+        assert_eq!(public.len(), 4);
+        assert_eq!(public[0]["content"]["text"], "root");
+
+        let all = index.thread(
+            &TanglesThread::new(root_id.clone())
+                .keys_values(true, false)
+                .private(true),
+        );
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[4], Value::String(d.id().to_string()));
+
+        let limited = index.thread(
+            &TanglesThread::new(root_id)
+                .keys_values(true, true)
+                .private(true)
+                .limit(2),
+        );
+        assert_eq!(limited.len(), 2);
+        assert_eq!(limited[0]["key"], Value::String(root.id().to_string()));
+        Ok(())
+    }
+}
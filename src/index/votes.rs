@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{is_newer, Version};
+use crate::{
+    api::dto::content::{TypedMessage, Vote},
+    feed::{Content, Message},
+};
+
+/// Expression of likes that do not set one.
+pub const DEFAULT_EXPRESSION: &str = "Like";
+
+/// The latest vote of a feed on a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoteState {
+    pub vote: Vote,
+    version: Version,
+}
+
+impl VoteState {
+    pub fn is_like(&self) -> bool {
+        self.vote.value.is_like()
+    }
+}
+
+/// Reactions to a message, counting only the current vote of each feed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VoteSummary {
+    /// Number of feeds currently liking the message.
+    pub likes: usize,
+    /// Number of likes by expression, e.g. `Like` or an emoji.
+    pub expressions: BTreeMap<String, usize>,
+    /// Feeds currently liking the message, sorted.
+    pub voters: Vec<String>,
+}
+
+/// Index of the votes of each feed on each message.
+#[derive(Debug, Default)]
+pub struct VoteIndex {
+    votes: HashMap<String, HashMap<String, VoteState>>,
+}
+
+impl VoteIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a `vote` message, returns if it changed the vote of its author.
+    pub fn apply(&mut self, msg: &Message) -> bool {
+        let vote = match msg.typed_content() {
+            Content::Typed(TypedMessage::Vote { vote }) => vote,
+            _ => return false,
+        };
+
+        let version = (msg.timestamp(), msg.sequence());
+        let votes = self.votes.entry(vote.link.clone()).or_default();
+        let last = votes.get(msg.author()).map(|state| &state.version);
+        if !is_newer(last, version) {
+            return false;
+        }
+        votes.insert(msg.author().clone(), VoteState { vote, version });
+        true
+    }
+
+    /// The current vote of `voter` on `target`.
+    pub fn vote(&self, target: &str, voter: &str) -> Option<&VoteState> {
+        self.votes.get(target).and_then(|votes| votes.get(voter))
+    }
+
+    /// Aggregate the current votes on `target`.
+    pub fn summary(&self, target: &str) -> VoteSummary {
+        let mut summary = VoteSummary::default();
+        for (voter, state) in self.votes.get(target).into_iter().flatten() {
+            if !state.is_like() {
+                continue;
+            }
+            let expression = state
+                .vote
+                .expression
+                .clone()
+                .unwrap_or_else(|| DEFAULT_EXPRESSION.to_string());
+            *summary.expressions.entry(expression).or_default() += 1;
+            summary.voters.push(voter.clone());
+            summary.likes += 1;
+        }
+        summary.voters.sort_unstable();
+        summary
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        api::dto::content::VoteValue,
+        feed::{Error, Result},
+        keystore::OwnedIdentity,
+    };
+
+    const MSG: &str = "%seUEAo7PTyA7vNwnOrmGIsUFfpyRzOvzGVv1QCb/Fz8=.sha256";
+
+    fn vote(prev: Option<&Message>, id: &OwnedIdentity, vote: Vote) -> Result<Message> {
+        let content = serde_json::to_value(TypedMessage::from(vote)).map_err(Error::from)?;
+        Message::sign(prev, id, content)
+    }
+
+    #[test]
+    fn test_votes() -> Result<()> {
+        let ids: Vec<_> = (0..3).map(|_| OwnedIdentity::create()).collect();
+        let like = || Vote::new(MSG.to_string(), VoteValue::Numeric(1));
+
+        let a1 = vote(None, &ids[0], like())?;
+        let b1 = vote(None, &ids[1], like().expression("🎉".to_string()))?;
+        let c1 = vote(
+            None,
+            &ids[2],
+            Vote::new(MSG.to_string(), VoteValue::Boolean(true)),
+        )?;
+        let c2 = vote(
+            Some(&c1),
+            &ids[2],
+            Vote::new(MSG.to_string(), VoteValue::Numeric(0)).expression("Unlike".to_string()),
+        )?;
+
+        let mut index = VoteIndex::new();
+        for msg in [&a1, &c2, &b1, &c1] {
+            index.apply(msg);
+        }
+
+        let summary = index.summary(MSG);
+        let mut voters = vec![ids[0].id.clone(), ids[1].id.clone()];
+        voters.sort_unstable();
+        assert_eq!(summary.likes, 2);
+        assert_eq!(summary.voters, voters);
+        assert_eq!(summary.expressions[DEFAULT_EXPRESSION], 1);
+        assert_eq!(summary.expressions["🎉"], 1);
+
+        let c = index.vote(MSG, &ids[2].id).unwrap();
+        assert!(!c.is_like());
+        assert_eq!(c.vote.expression.as_deref(), Some("Unlike"));
+        assert_eq!(index.summary("%other"), VoteSummary::default());
+        Ok(())
+    }
+}
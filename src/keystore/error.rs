@@ -6,6 +6,12 @@ pub enum Error {
     HomeNotFound,
     #[error("invalid configuration file")]
     InvalidConfig,
+    #[error("invalid mnemonic length: {0} words")]
+    InvalidMnemonicLength(usize),
+    #[error("unknown mnemonic word: {0}")]
+    UnknownMnemonicWord(String),
+    #[error("invalid mnemonic checksum")]
+    InvalidMnemonicChecksum,
     #[error("json deserialization")]
     Serde(#[from] serde_json::Error),
     #[error("crypto format")]
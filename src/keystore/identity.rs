@@ -1,6 +1,8 @@
-use crate::crypto::CURVE_ED25519_SUFFIX;
+use crate::crypto::{curve25519, shared_secret, CURVE_ED25519_SUFFIX};
 use kuska_sodiumoxide::crypto::sign::ed25519;
 
+use super::error::Result;
+
 /// Ed25519 signature scheme identifier.
 pub const CURVE_ED25519: &str = "ed25519";
 
@@ -28,4 +30,19 @@ impl OwnedIdentity {
             id: format!("@{}{}", base64::encode(&pk), CURVE_ED25519_SUFFIX),
         }
     }
+
+    /// Identity with the keypair derived from an ed25519 seed.
+    pub fn from_seed(seed: &ed25519::Seed) -> OwnedIdentity {
+        let (pk, sk) = ed25519::keypair_from_seed(seed);
+        OwnedIdentity {
+            pk,
+            sk,
+            id: format!("@{}{}", base64::encode(&pk), CURVE_ED25519_SUFFIX),
+        }
+    }
+
+    /// Diffie-hellman shared secret with the owner of `peer_pk`.
+    pub fn shared_secret(&self, peer_pk: &ed25519::PublicKey) -> Result<curve25519::GroupElement> {
+        Ok(shared_secret(&self.sk, peer_pk)?)
+    }
 }
@@ -0,0 +1,205 @@
+//! Backup and restore an identity as a list of words.
+//!
+//! The encoding is the one used by ssb-keys-mnemonic: the 32-byte ed25519
+//! seed is split in two halves and each one is encoded as a 12 word BIP39
+//! mnemonic, giving 24 words in total.
+
+use kuska_sodiumoxide::crypto::{hash::sha256, sign::ed25519};
+use once_cell::sync::Lazy;
+
+use super::{
+    error::{Error, Result},
+    OwnedIdentity,
+};
+
+/// Number of words of a mnemonic.
+pub const MNEMONIC_WORDS: usize = 24;
+
+const HALF_WORDS: usize = MNEMONIC_WORDS / 2;
+const HALF_SEED_LEN: usize = ed25519::SEEDBYTES / 2;
+const BITS_PER_WORD: usize = 11;
+
+static WORDLIST: Lazy<Vec<&'static str>> =
+    Lazy::new(|| include_str!("bip39_english.txt").lines().collect());
+
+/// Encode the seed of an `OwnedIdentity` as a mnemonic.
+pub fn to_mnemonic(id: &OwnedIdentity) -> String {
+    let seed = &id.sk[..ed25519::SEEDBYTES];
+    let mut words = entropy_to_words(&seed[..HALF_SEED_LEN]);
+    words.extend(entropy_to_words(&seed[HALF_SEED_LEN..]));
+    words.join(" ")
+}
+
+/// Decode a mnemonic into an `OwnedIdentity`.
+///
+/// Words are separated by whitespace and are case insensitive.
+pub fn from_mnemonic(mnemonic: &str) -> Result<OwnedIdentity> {
+    let words: Vec<_> = mnemonic
+        .split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect();
+
+    if words.len() != MNEMONIC_WORDS {
+        return Err(Error::InvalidMnemonicLength(words.len()));
+    }
+
+    let mut seed = words_to_entropy(&words[..HALF_WORDS])?;
+    seed.extend(words_to_entropy(&words[HALF_WORDS..])?);
+
+    let seed = ed25519::Seed::from_slice(&seed).ok_or(Error::InvalidMnemonicChecksum)?;
+    Ok(OwnedIdentity::from_seed(&seed))
+}
+
+fn checksum_bits(entropy: &[u8]) -> usize {
+    entropy.len() * 8 / 32
+}
+
+fn bit_at(bytes: &[u8], index: usize) -> bool {
+    bytes[index / 8] & (0x80 >> (index % 8)) != 0
+}
+
+fn entropy_to_words(entropy: &[u8]) -> Vec<&'static str> {
+    let digest = sha256::hash(entropy);
+    let entropy_bits = entropy.len() * 8;
+    let total_bits = entropy_bits + checksum_bits(entropy);
+
+    (0..total_bits / BITS_PER_WORD)
+        .map(|word_no| {
+            let index = (0..BITS_PER_WORD).fold(0, |index, bit_no| {
+                let bit = word_no * BITS_PER_WORD + bit_no;
+                let set = if bit < entropy_bits {
+                    bit_at(entropy, bit)
+                } else {
+                    bit_at(&digest[..], bit - entropy_bits)
+                };
+                (index << 1) | set as usize
+            });
+            WORDLIST[index]
+        })
+        .collect()
+}
+
+fn words_to_entropy(words: &[String]) -> Result<Vec<u8>> {
+    let total_bits = words.len() * BITS_PER_WORD;
+    let mut bits = Vec::with_capacity(total_bits);
+    for word in words {
+        let index = WORDLIST
+            .binary_search(&word.as_str())
+            .map_err(|_| Error::UnknownMnemonicWord(word.clone()))?;
+        bits.extend(
+            (0..BITS_PER_WORD)
+                .rev()
+                .map(|bit_no| index & (1 << bit_no) != 0),
+        );
+    }
+
+    let entropy_bits = total_bits * 32 / 33;
+    let entropy: Vec<u8> = bits[..entropy_bits]
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, bit| (acc << 1) | *bit as u8))
+        .collect();
+
+    let digest = sha256::hash(&entropy);
+    let checksum_ok = bits[entropy_bits..]
+        .iter()
+        .enumerate()
+        .all(|(bit_no, bit)| bit_at(&digest[..], bit_no) == *bit);
+    if !checksum_ok {
+        return Err(Error::InvalidMnemonicChecksum);
+    }
+
+    Ok(entropy)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // BIP39 reference vectors (english, no passphrase) for 128 bits of entropy.
+    const VECTORS: [(&str, &str); 4] = [
+        (
+            "00000000000000000000000000000000",
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        ),
+        (
+            "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+            "legal winner thank year wave sausage worth useful legal winner thank yellow",
+        ),
+        (
+            "80808080808080808080808080808080",
+            "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+        ),
+        (
+            "ffffffffffffffffffffffffffffffff",
+            "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+        ),
+    ];
+
+    #[test]
+    fn test_bip39_vectors() -> Result<()> {
+        for (entropy, mnemonic) in VECTORS.iter() {
+            let entropy = hex::decode(entropy).unwrap();
+            assert_eq!(entropy_to_words(&entropy).join(" "), *mnemonic);
+
+            let words: Vec<_> = mnemonic.split(' ').map(String::from).collect();
+            assert_eq!(words_to_entropy(&words)?, entropy);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mnemonic_zero_seed() -> Result<()> {
+        let half = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let id = from_mnemonic(&format!("{} {}", half, half.to_uppercase()))?;
+        assert_eq!(
+            id.id,
+            "@O2onvM62pC1io6jQKm8Nc2UyFXcd4kOmOsBIoYtZ2ik=.ed25519"
+        );
+        assert_eq!(to_mnemonic(&id), format!("{} {}", half, half));
+        Ok(())
+    }
+
+    // The seed of RFC 8032 test 1, whose public key is known.
+    const RFC8032_MNEMONIC: &str = "output assault guess that stick core tube matter virus number arctic match dust evolve fog wait charge million science intact adapt tornado panther science";
+
+    #[test]
+    fn test_mnemonic_vector() -> Result<()> {
+        let id = from_mnemonic(RFC8032_MNEMONIC)?;
+        assert_eq!(
+            id.id,
+            "@11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=.ed25519"
+        );
+        assert_eq!(
+            hex::encode(&id.sk[..ed25519::SEEDBYTES]),
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60"
+        );
+        assert_eq!(to_mnemonic(&id), RFC8032_MNEMONIC);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mnemonic_roundtrip() -> Result<()> {
+        let id = OwnedIdentity::create();
+        let mnemonic = to_mnemonic(&id);
+        assert_eq!(mnemonic.split(' ').count(), MNEMONIC_WORDS);
+        assert_eq!(from_mnemonic(&mnemonic)?, id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_mnemonic_invalid() {
+        let half = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon";
+        assert!(matches!(
+            from_mnemonic(&format!("{} {}", half, half)),
+            Err(Error::InvalidMnemonicChecksum)
+        ));
+        assert!(matches!(
+            from_mnemonic(half),
+            Err(Error::InvalidMnemonicLength(12))
+        ));
+        assert!(matches!(
+            from_mnemonic(&format!("{} {} kuska", half, half)),
+            Err(Error::InvalidMnemonicLength(25))
+        ));
+    }
+}
@@ -1,6 +1,7 @@
 mod error;
 pub mod gosbot;
 mod identity;
+pub mod mnemonic;
 pub mod patchwork;
 mod util;
 
@@ -8,6 +9,7 @@ pub use gosbot::{
     from_custom_gosbot_keypath, from_gosbot_local, read_gosbot_config, write_gosbot_config,
 };
 pub use identity::{JsonSSBSecret, OwnedIdentity, CURVE_ED25519};
+pub use mnemonic::{from_mnemonic, to_mnemonic};
 pub use patchwork::{
     from_custom_patchwork_keypath, from_patchwork_local, read_patchwork_config,
     write_patchwork_config,
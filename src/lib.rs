@@ -7,8 +7,10 @@ extern crate serde_json;
 extern crate thiserror;
 
 pub mod api;
+pub mod blobs;
 pub mod crypto;
 pub mod discovery;
 pub mod feed;
+pub mod index;
 pub mod keystore;
 pub mod rpc;
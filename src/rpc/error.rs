@@ -1,11 +1,33 @@
 use thiserror::Error;
 
+use super::{ErrorMessage, RequestNo};
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("header size too small")]
     HeaderSizeTooSmall,
     #[error("invalid body type: {0}")]
     InvalidBodyType(u8),
+    /// `req_no` is the one on the wire: positive on requests of the peer,
+    /// negative on responses to ours.
+    #[error("body of {len} bytes too large, on request {req_no}")]
+    BodyTooLarge { req_no: RequestNo, len: u32 },
+    #[error("stream already ended")]
+    StreamEnded,
+    #[error("queue of stream {0} overflowed")]
+    StreamOverflow(RequestNo),
+    #[error("remote error: {0}")]
+    Remote(ErrorMessage),
+    #[error("request {0} timed out")]
+    Timeout(RequestNo),
+    #[error("peer missed {0} pings")]
+    PingTimeout(u32),
+    #[error("handshake failed: {0}")]
+    Handshake(String),
+    #[error("invalid record: {0}")]
+    InvalidRecord(String),
+    #[error("connection closed")]
+    Closed,
     #[error("i/o")]
     Io(#[from] async_std::io::Error),
     #[error("json decoding")]
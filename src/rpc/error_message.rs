@@ -0,0 +1,140 @@
+use std::fmt;
+
+/// Name of the generic errors.
+pub const ERROR_NAME: &str = "Error";
+const NOT_FOUND_NAME: &str = "NotFoundError";
+const NOT_ALLOWED_PREFIX: &str = "method:";
+const NOT_ALLOWED_SUFFIX: &str = "is not in list of allowed methods";
+
+fn default_name() -> String {
+    ERROR_NAME.to_string()
+}
+
+/// The well-known errors of muxrpc peers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// `NotFoundError`, e.g. when getting an unknown message.
+    NotFound,
+    /// The peer does not allow calling the method, with its dotted name.
+    NotAllowed(String),
+    Other,
+}
+
+/// An error sent over muxrpc, like a serialized javascript `Error`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorMessage {
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default)]
+    pub stack: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+impl ErrorMessage {
+    pub fn new(message: &str) -> Self {
+        ErrorMessage {
+            name: default_name(),
+            stack: String::new(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(message).name(NOT_FOUND_NAME)
+    }
+
+    /// The error for calls to a method that is not allowed, e.g. not
+    /// in the manifest.
+    pub fn not_allowed(method: &[&str]) -> Self {
+        Self::new(&format!(
+            "{}{} {}",
+            NOT_ALLOWED_PREFIX,
+            method.join("."),
+            NOT_ALLOWED_SUFFIX
+        ))
+    }
+
+    pub fn name(self, name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            ..self
+        }
+    }
+
+    pub fn stack(self, stack: &str) -> Self {
+        Self {
+            stack: stack.to_string(),
+            ..self
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        if self.name == NOT_FOUND_NAME {
+            return ErrorKind::NotFound;
+        }
+        // some peers send the whole `Error: message` string
+        let message = self
+            .message
+            .strip_prefix("Error: ")
+            .unwrap_or(&self.message);
+        let method = message
+            .strip_prefix(NOT_ALLOWED_PREFIX)
+            .and_then(|method| method.strip_suffix(NOT_ALLOWED_SUFFIX));
+        match method {
+            Some(method) => ErrorKind::NotAllowed(method.trim().to_string()),
+            None => ErrorKind::Other,
+        }
+    }
+
+    /// Parse an error body, bodies that are not an error object are kept as
+    /// the message.
+    pub fn from_slice(body: &[u8]) -> Self {
+        serde_json::from_slice(body)
+            .unwrap_or_else(|_| Self::new(String::from_utf8_lossy(body).trim_matches('"')))
+    }
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+impl std::error::Error for ErrorMessage {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_kinds() -> serde_json::Result<()> {
+        let err = ErrorMessage::from_slice(
+            br#"{"name":"NotFoundError","message":"Key not found in database","stack":"at get"}"#,
+        );
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.stack, "at get");
+
+        let err = ErrorMessage::from_slice(
+            br#"{"message":"Error: method:blobs.add is not in list of allowed methods"}"#,
+        );
+        assert_eq!(err.name, ERROR_NAME);
+        assert_eq!(err.kind(), ErrorKind::NotAllowed("blobs.add".to_string()));
+        assert_eq!(
+            ErrorMessage::not_allowed(&["blobs", "add"]).kind(),
+            err.kind()
+        );
+
+        let err = ErrorMessage::from_slice(b"unexpected end of parent stream");
+        assert_eq!(err.message, "unexpected end of parent stream");
+        assert_eq!(err.kind(), ErrorKind::Other);
+
+        let err = ErrorMessage::new("boom").name("TypeError");
+        assert_eq!(err.to_string(), "TypeError: boom");
+        assert_eq!(
+            serde_json::to_string(&err)?,
+            r#"{"name":"TypeError","stack":"","message":"boom"}"#
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::{Duration, Instant};
+
+use super::{
+    error::{Error, Result},
+    stream::RequestNo,
+};
+
+/// Default number of pings a peer can leave unanswered.
+pub const DEFAULT_MAX_MISSED_PINGS: u32 = 3;
+
+/// Ping based keepalive, e.g. over `gossip.ping`: a ping is sent after
+/// `interval` without hearing from the peer, and the peer is dropped after
+/// missing `max_missed` of them in a row.
+///
+/// See `ApiCaller::recv_keepalive` for a driver over a connection.
+#[derive(Debug, Clone)]
+pub struct Keepalive {
+    interval: Duration,
+    max_missed: u32,
+    missed: u32,
+    waiting: bool,
+    last_event: Instant,
+    // our `gossip.ping` request, once opened
+    req_no: Option<RequestNo>,
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration) -> Self {
+        Keepalive {
+            interval,
+            max_missed: DEFAULT_MAX_MISSED_PINGS,
+            missed: 0,
+            waiting: false,
+            last_event: Instant::now(),
+            req_no: None,
+        }
+    }
+
+    pub fn max_missed(self, max_missed: u32) -> Self {
+        Self { max_missed, ..self }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Number of pings missed in a row.
+    pub fn missed(&self) -> u32 {
+        self.missed
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.missed < self.max_missed
+    }
+
+    /// Time left until the next ping is due.
+    pub fn until_ping(&self) -> Duration {
+        self.interval.saturating_sub(self.last_event.elapsed())
+    }
+
+    pub(crate) fn req_no(&self) -> Option<RequestNo> {
+        self.req_no
+    }
+
+    pub(crate) fn set_req_no(&mut self, req_no: Option<RequestNo>) {
+        self.req_no = req_no;
+    }
+
+    /// Register a ping about to be sent, counting the previous one as
+    /// missed if still unanswered. Fails with `Error::PingTimeout` once the
+    /// peer missed too many, it should then be dropped.
+    pub fn ping(&mut self) -> Result<()> {
+        if self.waiting {
+            self.missed += 1;
+        }
+        self.waiting = true;
+        self.last_event = Instant::now();
+        if self.is_alive() {
+            Ok(())
+        } else {
+            Err(Error::PingTimeout(self.missed))
+        }
+    }
+
+    /// Register an answer of the peer, any message can count as one.
+    pub fn pong(&mut self) {
+        self.missed = 0;
+        self.waiting = false;
+        self.last_event = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keepalive() {
+        let mut keepalive = Keepalive::new(Duration::from_secs(10)).max_missed(2);
+        assert!(keepalive.ping().is_ok());
+        keepalive.pong();
+        assert!(keepalive.ping().is_ok());
+        assert!(keepalive.ping().is_ok());
+        assert_eq!(keepalive.missed(), 1);
+        assert!(matches!(keepalive.ping(), Err(Error::PingTimeout(2))));
+        assert!(!keepalive.is_alive());
+        keepalive.pong();
+        assert!(keepalive.is_alive());
+    }
+}
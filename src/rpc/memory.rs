@@ -0,0 +1,559 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use async_std::io;
+use futures::{ready, Future};
+use kuska_handshake::async_std::{handshake_client, handshake_server, BoxStream};
+use kuska_sodiumoxide::crypto::auth;
+
+use super::{
+    error::{Error, Result},
+    stream::{RpcReader, RpcWriter, HEADER_SIZE},
+};
+use crate::keystore::OwnedIdentity;
+
+const BOX_STREAM_CAPACITY: usize = 0x8000;
+
+#[derive(Debug, Default)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+impl PipeState {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Reading end of an in-memory pipe.
+#[derive(Debug)]
+pub struct PipeReader(Arc<Mutex<PipeState>>);
+
+/// Writing end of an in-memory pipe, the reader gets an EOF once it is
+/// closed or dropped.
+#[derive(Debug)]
+pub struct PipeWriter(Arc<Mutex<PipeState>>);
+
+/// An unbounded in-memory pipe.
+pub fn pipe() -> (PipeWriter, PipeReader) {
+    let state = Arc::new(Mutex::new(PipeState::default()));
+    (PipeWriter(state.clone()), PipeReader(state))
+}
+
+impl io::Read for PipeReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.0.lock().unwrap();
+        if pipe.buf.is_empty() && !buf.is_empty() {
+            if pipe.closed {
+                return Poll::Ready(Ok(0));
+            }
+            pipe.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let len = std::cmp::min(buf.len(), pipe.buf.len());
+        for (dst, src) in buf.iter_mut().zip(pipe.buf.drain(..len)) {
+            *dst = src;
+        }
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl PipeWriter {
+    fn close(&self) {
+        let mut pipe = self.0.lock().unwrap();
+        pipe.closed = true;
+        pipe.wake();
+    }
+}
+
+impl io::Write for PipeWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut pipe = self.0.lock().unwrap();
+        if pipe.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        pipe.buf.extend(buf);
+        pipe.wake();
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Both ends a peer uses during the handshake.
+struct Duplex<'a> {
+    reader: &'a mut PipeReader,
+    writer: &'a mut PipeWriter,
+}
+
+impl<'a> io::Read for Duplex<'a> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.get_mut().reader).poll_read(cx, buf)
+    }
+}
+
+impl<'a> io::Write for Duplex<'a> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut *self.get_mut().writer).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().writer).poll_close(cx)
+    }
+}
+
+/// A fault injected in a frame: an rpc frame, i.e. a header and its body,
+/// or a box-stream box on encrypted transports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Send the frame after waiting this long.
+    Delay(Duration),
+    /// Do not send the frame.
+    Drop,
+    /// Send only the first bytes of the frame, then close the connection.
+    Truncate(usize),
+    /// Flip the bits of the byte at this offset of the frame, the header
+    /// being its first 9 bytes in rpc frames.
+    Corrupt(usize),
+}
+
+/// Faults to inject in the frames written by a peer, by their index. With
+/// `MemoryTransport::connect_box` they are injected in the encrypted
+/// stream, counting each write of the box-stream encoder as a frame, so
+/// they reach its checks.
+#[derive(Debug, Clone, Default)]
+pub struct Faults {
+    faults: HashMap<usize, Fault>,
+}
+
+impl Faults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inject `fault` in the frame `frame`, counting from 0.
+    pub fn at(mut self, frame: usize, fault: Fault) -> Self {
+        self.faults.insert(frame, fault);
+        self
+    }
+
+    pub fn get(&self, frame: usize) -> Option<Fault> {
+        self.faults.get(&frame).copied()
+    }
+}
+
+/// Writer injecting `Faults` in the rpc frames written through it, or in
+/// each write when created with `FaultyWriter::raw`.
+pub struct FaultyWriter<W> {
+    inner: W,
+    faults: Faults,
+    // every write is a frame
+    raw: bool,
+    // index of the frame being written
+    frame: usize,
+    // incomplete frame
+    pending: Vec<u8>,
+    // complete frame, to send to the inner writer
+    out: Vec<u8>,
+    sent: usize,
+    delay: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+    truncated: bool,
+    closed: bool,
+}
+
+impl<W: io::Write + Unpin> FaultyWriter<W> {
+    pub fn new(inner: W, faults: Faults) -> Self {
+        FaultyWriter {
+            inner,
+            faults,
+            raw: false,
+            frame: 0,
+            pending: Vec::new(),
+            out: Vec::new(),
+            sent: 0,
+            delay: None,
+            truncated: false,
+            closed: false,
+        }
+    }
+
+    /// Writer taking each write as a frame, e.g. below a box-stream encoder.
+    pub fn raw(inner: W, faults: Faults) -> Self {
+        Self {
+            raw: true,
+            ..Self::new(inner, faults)
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        if self.pending.len() < HEADER_SIZE {
+            return HEADER_SIZE;
+        }
+        let mut body_len = [0u8; 4];
+        body_len.copy_from_slice(&self.pending[1..5]);
+        HEADER_SIZE + u32::from_be_bytes(body_len) as usize
+    }
+
+    fn end_frame(&mut self) {
+        let mut frame = std::mem::take(&mut self.pending);
+        match self.faults.get(self.frame) {
+            Some(Fault::Delay(delay)) => {
+                self.delay = Some(Box::pin(async_std::task::sleep(delay)));
+            }
+            Some(Fault::Drop) => frame.clear(),
+            Some(Fault::Truncate(len)) => {
+                frame.truncate(len);
+                self.truncated = true;
+            }
+            Some(Fault::Corrupt(offset)) => {
+                if let Some(byte) = frame.get_mut(offset) {
+                    *byte ^= 0xff;
+                }
+            }
+            None => {}
+        }
+        self.out = frame;
+        self.frame += 1;
+    }
+
+    /// Send the last complete frame, closing the inner writer if it was
+    /// truncated.
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(delay) = &mut self.delay {
+            ready!(delay.as_mut().poll(cx));
+            self.delay = None;
+        }
+        while self.sent < self.out.len() {
+            let len = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.sent..]))?;
+            if len == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.sent += len;
+        }
+        self.out.clear();
+        self.sent = 0;
+        if self.truncated && !self.closed {
+            ready!(Pin::new(&mut self.inner).poll_close(cx))?;
+            self.closed = true;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: io::Write + Unpin> io::Write for FaultyWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        if this.closed {
+            // the connection was cut, the rest is lost
+            return Poll::Ready(Ok(buf.len()));
+        }
+        // take at most the rest of the current frame
+        let len = if this.raw {
+            buf.len()
+        } else {
+            std::cmp::min(buf.len(), this.frame_len() - this.pending.len())
+        };
+        this.pending.extend_from_slice(&buf[..len]);
+        if this.raw || this.pending.len() == this.frame_len() {
+            this.end_frame();
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        if this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send(cx))?;
+        if this.closed {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+pub type MemoryRead = Box<dyn io::Read + Unpin + Send>;
+pub type MemoryWrite = Box<dyn io::Write + Unpin + Send>;
+
+/// The reader and writer of one of the peers of a `MemoryTransport`.
+pub type MemoryPeer = (RpcReader<MemoryRead>, RpcWriter<MemoryWrite>);
+
+/// In-process transport connecting a client and a server, to test rpc
+/// clients and handlers without sockets.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransport {
+    client_faults: Faults,
+    server_faults: Faults,
+}
+
+impl MemoryTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Faults to inject in the frames written by the client.
+    pub fn client_faults(self, client_faults: Faults) -> Self {
+        Self {
+            client_faults,
+            ..self
+        }
+    }
+
+    /// Faults to inject in the frames written by the server.
+    pub fn server_faults(self, server_faults: Faults) -> Self {
+        Self {
+            server_faults,
+            ..self
+        }
+    }
+
+    /// Connect the client and the server without encryption, returns their
+    /// readers and writers.
+    pub fn connect(self) -> (MemoryPeer, MemoryPeer) {
+        let (client_writer, server_reader) = pipe();
+        let (server_writer, client_reader) = pipe();
+        (
+            Self::peer(
+                client_reader,
+                FaultyWriter::new(client_writer, self.client_faults),
+            ),
+            Self::peer(
+                server_reader,
+                FaultyWriter::new(server_writer, self.server_faults),
+            ),
+        )
+    }
+
+    /// Connect the client and the server with a secret handshake, and then
+    /// box-stream, returns their readers and writers.
+    pub async fn connect_box(
+        self,
+        net_id: auth::Key,
+        client: &OwnedIdentity,
+        server: &OwnedIdentity,
+    ) -> Result<(MemoryPeer, MemoryPeer)> {
+        let (mut client_writer, mut server_reader) = pipe();
+        let (mut server_writer, mut client_reader) = pipe();
+
+        let (client_handshake, server_handshake) = futures::join!(
+            handshake_client(
+                Duplex {
+                    reader: &mut client_reader,
+                    writer: &mut client_writer,
+                },
+                net_id.clone(),
+                client.pk,
+                client.sk.clone(),
+                server.pk,
+            ),
+            handshake_server(
+                Duplex {
+                    reader: &mut server_reader,
+                    writer: &mut server_writer,
+                },
+                net_id,
+                server.pk,
+                server.sk.clone(),
+            ),
+        );
+        let client_handshake = client_handshake.map_err(|err| Error::Handshake(err.to_string()))?;
+        let server_handshake = server_handshake.map_err(|err| Error::Handshake(err.to_string()))?;
+
+        // faults are injected in the encrypted stream, after the handshake
+        let client_writer = FaultyWriter::raw(client_writer, self.client_faults);
+        let server_writer = FaultyWriter::raw(server_writer, self.server_faults);
+        let (client_reader, client_writer) = BoxStream::from_handshake(
+            client_reader,
+            client_writer,
+            client_handshake,
+            BOX_STREAM_CAPACITY,
+        )
+        .split_read_write();
+        let (server_reader, server_writer) = BoxStream::from_handshake(
+            server_reader,
+            server_writer,
+            server_handshake,
+            BOX_STREAM_CAPACITY,
+        )
+        .split_read_write();
+
+        Ok((
+            Self::peer(client_reader, client_writer),
+            Self::peer(server_reader, server_writer),
+        ))
+    }
+
+    fn peer<R, W>(reader: R, writer: W) -> MemoryPeer
+    where
+        R: io::Read + Unpin + Send + 'static,
+        W: io::Write + Unpin + Send + 'static,
+    {
+        let reader: MemoryRead = Box::new(reader);
+        let writer: MemoryWrite = Box::new(writer);
+        let reader = RpcReader::plain(reader);
+        let writer = RpcWriter::plain(writer).peer_streams(reader.peer_streams());
+        (reader, writer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        discovery::ssb_net_id,
+        rpc::{ArgType, BodyType, RecvMsg, RpcType},
+    };
+    use async_std::task::block_on;
+    use std::time::Instant;
+
+    async fn whoami(writer: &mut RpcWriter<MemoryWrite>) -> Result<i32> {
+        let args: [&str; 0] = [];
+        writer
+            .send_request(
+                &["whoami"],
+                RpcType::Async,
+                ArgType::Array,
+                &args,
+                &None::<()>,
+            )
+            .await
+    }
+
+    async fn roundtrip(client: &mut MemoryPeer, server: &mut MemoryPeer) -> Result<()> {
+        let req_no = whoami(&mut client.1).await?;
+        match server.0.recv().await? {
+            (no, RecvMsg::RpcRequest(body)) if no == req_no => assert_eq!(body.name, ["whoami"]),
+            other => panic!("unexpected {:?}", other),
+        }
+        server
+            .1
+            .send_response(req_no, RpcType::Async, BodyType::JSON, b"{}")
+            .await?;
+        assert!(matches!(
+            client.0.recv().await?,
+            (no, RecvMsg::RpcResponse(BodyType::JSON, _)) if no == req_no
+        ));
+
+        client.1.close().await?;
+        assert!(matches!(server.0.recv().await?, (0, RecvMsg::Goodbye)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_transport() -> Result<()> {
+        let (mut client, mut server) = MemoryTransport::new().connect();
+        block_on(roundtrip(&mut client, &mut server))
+    }
+
+    #[test]
+    fn test_memory_transport_box() -> Result<()> {
+        let (alice, bob) = (OwnedIdentity::create(), OwnedIdentity::create());
+        block_on(async {
+            let (mut client, mut server) = MemoryTransport::new()
+                .connect_box(ssb_net_id(), &alice, &bob)
+                .await?;
+            roundtrip(&mut client, &mut server).await
+        })
+    }
+
+    #[test]
+    fn test_faults() -> Result<()> {
+        let faults = Faults::new()
+            .at(0, Fault::Delay(Duration::from_millis(20)))
+            .at(1, Fault::Drop)
+            .at(2, Fault::Corrupt(HEADER_SIZE))
+            .at(4, Fault::Truncate(5));
+        let ((_, mut writer), (mut reader, _)) =
+            MemoryTransport::new().client_faults(faults).connect();
+
+        block_on(async {
+            let start = Instant::now();
+            assert_eq!(whoami(&mut writer).await?, 1);
+            assert!(start.elapsed() >= Duration::from_millis(20));
+            for _ in 2..=5 {
+                whoami(&mut writer).await?;
+            }
+
+            assert!(matches!(reader.recv().await?, (1, RecvMsg::RpcRequest(_))));
+            // the second one was dropped, and the body of the third corrupted
+            assert!(matches!(
+                reader.recv().await?,
+                (3, RecvMsg::OtherRequest(..))
+            ));
+            assert!(matches!(reader.recv().await?, (4, RecvMsg::RpcRequest(_))));
+            assert!(matches!(reader.recv().await, Err(Error::Io(_))));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_faults_box() -> Result<()> {
+        let (alice, bob) = (OwnedIdentity::create(), OwnedIdentity::create());
+        block_on(async {
+            for fault in [Fault::Corrupt(0), Fault::Truncate(5)] {
+                let ((_, mut writer), (mut reader, _)) = MemoryTransport::new()
+                    .client_faults(Faults::new().at(0, fault))
+                    .connect_box(ssb_net_id(), &alice, &bob)
+                    .await?;
+                whoami(&mut writer).await?;
+                // rejected by box-stream
+                assert!(matches!(reader.recv().await, Err(Error::Io(_))));
+            }
+            Ok(())
+        })
+    }
+}
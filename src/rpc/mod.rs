@@ -1,5 +1,23 @@
 mod error;
+mod error_message;
+mod keepalive;
+mod memory;
+mod recorder;
 mod stream;
+mod streams;
 
 pub use error::{Error, Result};
-pub use stream::{ArgType, Body, BodyType, RecvMsg, RequestNo, RpcReader, RpcType, RpcWriter};
+pub use error_message::{ErrorKind, ErrorMessage, ERROR_NAME};
+pub use keepalive::{Keepalive, DEFAULT_MAX_MISSED_PINGS};
+pub use memory::{
+    pipe, Fault, Faults, FaultyWriter, MemoryPeer, MemoryRead, MemoryTransport, MemoryWrite,
+    PipeReader, PipeWriter,
+};
+pub use recorder::{Direction, Record, RecordedBody, Recorder, Replay};
+pub use stream::{
+    ArgType, Body, BodyType, Header, PeerStreams, RecvMsg, RequestNo, RpcReader, RpcType,
+    RpcWriter, DEFAULT_MAX_BODY_LEN,
+};
+pub use streams::{
+    AsyncResponse, PauseHandle, RpcSink, RpcStream, StreamRouter, DEFAULT_STREAM_CAPACITY,
+};
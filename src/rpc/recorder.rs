@@ -0,0 +1,390 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use async_std::prelude::*;
+use futures::Future;
+use log::warn;
+
+use super::{
+    error::{Error, Result},
+    memory::{pipe, MemoryRead, MemoryWrite},
+    stream::{Header, RpcReader, RpcWriter, HEADER_SIZE},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// A message body, as text if it is valid utf-8 and as base64 otherwise.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedBody {
+    Text(String),
+    Base64(String),
+    /// The start of a body longer than the `max_body_len` of the reader, its
+    /// whole length being in the header.
+    Truncated(Box<RecordedBody>),
+}
+
+impl RecordedBody {
+    pub fn new(body: &[u8]) -> Self {
+        match std::str::from_utf8(body) {
+            Ok(text) => RecordedBody::Text(text.to_string()),
+            Err(_) => RecordedBody::Base64(base64::encode(body)),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        match self {
+            RecordedBody::Text(text) => Ok(text.as_bytes().to_vec()),
+            RecordedBody::Base64(encoded) => {
+                base64::decode(encoded).map_err(|err| Error::InvalidRecord(err.to_string()))
+            }
+            RecordedBody::Truncated(_) => Err(Error::InvalidRecord("body truncated".to_string())),
+        }
+    }
+}
+
+/// A message sent or received, as written by a `Recorder`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// Milliseconds since the unix epoch.
+    pub timestamp: f64,
+    pub direction: Direction,
+    pub header: Header,
+    /// Always written by a `Recorder`, but truncated if it is longer than
+    /// the `max_body_len` of the reader.
+    pub body: Option<RecordedBody>,
+}
+
+impl Record {
+    pub fn new(direction: Direction, header: &Header, body: Option<&[u8]>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as f64;
+        Record {
+            timestamp,
+            direction,
+            header: header.clone(),
+            body: body.map(|body| {
+                // shorter than in the header when cut by the reader
+                if body.len() < header.body_len as usize {
+                    RecordedBody::Truncated(Box::new(RecordedBody::new(body)))
+                } else {
+                    RecordedBody::new(body)
+                }
+            }),
+        }
+    }
+
+    /// Check if the whole body was recorded, records without it are skipped
+    /// by `Replay::run`.
+    pub fn has_body(&self) -> bool {
+        matches!(
+            self.body,
+            Some(RecordedBody::Text(_)) | Some(RecordedBody::Base64(_))
+        )
+    }
+
+    /// Check if both records are the same message, no matter when they
+    /// were recorded.
+    pub fn same_message(&self, other: &Record) -> bool {
+        self.direction == other.direction && self.header == other.header && self.body == other.body
+    }
+
+    /// The message as sent on the wire.
+    pub fn to_frame(&self) -> Result<Vec<u8>> {
+        let body = match &self.body {
+            Some(body) => body.to_bytes()?,
+            None => return Err(Error::InvalidRecord("body not recorded".to_string())),
+        };
+        let mut frame = self.header.to_array().to_vec();
+        frame.extend_from_slice(&body);
+        Ok(frame)
+    }
+}
+
+/// Writes the messages of rpc readers and writers to a JSON-lines file,
+/// one `Record` per line.
+#[derive(Clone)]
+pub struct Recorder {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Recorder {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        Recorder {
+            out: Arc::new(Mutex::new(Box::new(out))),
+        }
+    }
+
+    /// Record to a new file at `path`, replacing any existing one.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(LineWriter::new(file)))
+    }
+
+    /// Record a message, failures are only logged.
+    pub fn record(&self, direction: Direction, header: &Header, body: Option<&[u8]>) {
+        let record = Record::new(direction, header, body);
+        if let Err(err) = self.write(&record) {
+            warn!(target: "ssb-rpc", "cannot record {:?}: {}", header, err);
+        }
+    }
+
+    fn write(&self, record: &Record) -> Result<()> {
+        let line = serde_json::to_string(record)?;
+        let mut out = self.out.lock().unwrap();
+        writeln!(out, "{}", line)?;
+        Ok(())
+    }
+}
+
+/// A recorded session, replayed to test a handler.
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    records: Vec<Record>,
+}
+
+impl Replay {
+    pub fn new(records: Vec<Record>) -> Self {
+        Replay { records }
+    }
+
+    /// Read the records written by a `Recorder`.
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<Self> {
+        let mut records = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                records.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Self::new(records))
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// The records of the messages sent in the session.
+    pub fn sent(&self) -> Vec<&Record> {
+        self.records
+            .iter()
+            .filter(|record| record.direction == Direction::Out)
+            .collect()
+    }
+
+    /// Feed the messages received in the session to `handler`, returning
+    /// the records of the messages it sent. Its reader ends after the last
+    /// message. Records without whole body are skipped.
+    pub async fn run<F, Fut>(&self, handler: F) -> Result<Vec<Record>>
+    where
+        F: FnOnce(RpcReader<MemoryRead>, RpcWriter<MemoryWrite>) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let (mut in_writer, in_reader) = pipe();
+        for record in &self.records {
+            if record.direction != Direction::In {
+                continue;
+            }
+            if !record.has_body() {
+                warn!(target: "ssb-rpc", "skipped record without whole body {:?}", record.header);
+                continue;
+            }
+            in_writer.write_all(&record.to_frame()?).await?;
+        }
+        drop(in_writer);
+
+        let (out_writer, mut out_reader) = pipe();
+        let reader: MemoryRead = Box::new(in_reader);
+        let writer: MemoryWrite = Box::new(out_writer);
+        let reader = RpcReader::plain(reader);
+        let writer = RpcWriter::plain(writer).peer_streams(reader.peer_streams());
+        handler(reader, writer).await?;
+
+        let mut sent = Vec::new();
+        out_reader.read_to_end(&mut sent).await?;
+        let mut records = Vec::new();
+        let mut frames = &sent[..];
+        while !frames.is_empty() {
+            let header = Header::from_slice(frames)?;
+            let end = HEADER_SIZE + header.body_len as usize;
+            if frames.len() < end {
+                return Err(Error::InvalidRecord("truncated message".to_string()));
+            }
+            records.push(Record::new(
+                Direction::Out,
+                &header,
+                Some(&frames[HEADER_SIZE..end]),
+            ));
+            frames = &frames[end..];
+        }
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::{ArgType, BodyType, MemoryTransport, RecvMsg, RpcType};
+    use async_std::task::block_on;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn handler(
+        mut reader: RpcReader<MemoryRead>,
+        mut writer: RpcWriter<MemoryWrite>,
+    ) -> Result<()> {
+        loop {
+            match reader.recv().await? {
+                (req_no, RecvMsg::RpcRequest(body)) if body.name == ["whoami"] => {
+                    writer
+                        .send_response(req_no, RpcType::Async, BodyType::JSON, br#"{"id":"@me"}"#)
+                        .await?
+                }
+                (req_no, RecvMsg::RpcRequest(_)) => {
+                    writer
+                        .send_response(req_no, RpcType::Async, BodyType::Binary, &[0xff, 0])
+                        .await?
+                }
+                (_, RecvMsg::Goodbye) => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_and_replay() -> Result<()> {
+        let buf = SharedBuf::default();
+        let recorder = Recorder::new(buf.clone());
+        let ((mut client_reader, mut client_writer), (server_reader, server_writer)) =
+            MemoryTransport::new().connect();
+
+        let client = async {
+            let args: [&str; 0] = [];
+            for name in ["whoami", "blob"] {
+                client_writer
+                    .send_request(&[name], RpcType::Async, ArgType::Array, &args, &None::<()>)
+                    .await?;
+                client_reader.recv().await?;
+            }
+            client_writer.close().await
+        };
+        let server = handler(
+            server_reader.record(recorder.clone()),
+            server_writer.record(recorder),
+        );
+        let (client, server) = block_on(async { futures::join!(client, server) });
+        client?;
+        server?;
+
+        let replay = Replay::from_reader(&buf.0.lock().unwrap()[..])?;
+        // two requests, two responses and the goodbye
+        assert_eq!(replay.records().len(), 5);
+        assert_eq!(
+            replay.sent()[1].body,
+            Some(RecordedBody::Base64("/wA=".to_string()))
+        );
+
+        let sent = block_on(replay.run(handler))?;
+        assert_eq!(sent.len(), 2);
+        for (replayed, recorded) in sent.iter().zip(replay.sent()) {
+            assert!(replayed.same_message(recorded));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_record_read_body() -> Result<()> {
+        let buf = SharedBuf::default();
+        let ((_, mut client_writer), (server_reader, _)) = MemoryTransport::new().connect();
+        let mut server_reader = server_reader
+            .max_body_len(4)
+            .record(Recorder::new(buf.clone()));
+
+        block_on(async {
+            client_writer
+                .send_response(1, RpcType::Source, BodyType::Binary, b"abc")
+                .await?;
+            client_writer
+                .send_response(2, RpcType::Source, BodyType::Binary, b"in pieces")
+                .await?;
+            client_writer
+                .send_response(3, RpcType::Async, BodyType::Binary, b"too long")
+                .await?;
+            client_writer.close().await?;
+
+            server_reader.recv_header().await?;
+            while server_reader.read_body(&mut [0u8; 2]).await? > 0 {}
+            server_reader.recv_header().await?;
+            assert_eq!(server_reader.read_body(&mut [0u8; 4]).await?, 4);
+            // skips the rest of the body
+            assert!(matches!(
+                server_reader.recv().await,
+                Err(Error::BodyTooLarge { req_no: -3, .. })
+            ));
+            assert!(matches!(server_reader.recv().await?, (0, RecvMsg::Goodbye)));
+            Ok::<_, Error>(())
+        })?;
+
+        // not buffered beyond the maximum length
+        let replay = Replay::from_reader(&buf.0.lock().unwrap()[..])?;
+        let bodies: Vec<_> = replay.records().iter().map(|r| r.body.clone()).collect();
+        let text = |text: &str| RecordedBody::Text(text.to_string());
+        let truncated = |prefix: &str| Some(RecordedBody::Truncated(Box::new(text(prefix))));
+        assert_eq!(
+            bodies,
+            vec![
+                Some(text("abc")),
+                truncated("in p"),
+                truncated("too "),
+                Some(text(""))
+            ]
+        );
+        assert!(matches!(
+            replay.records()[1].to_frame(),
+            Err(Error::InvalidRecord(_))
+        ));
+
+        // replayed without the truncated records, nor the ones without body
+        let mut records = replay.records().to_vec();
+        records.insert(0, Record::new(Direction::In, &records[0].header, None));
+        let replay = Replay::new(records);
+        let sent = block_on(replay.run(|mut reader, _| async move {
+            assert!(matches!(
+                reader.recv().await?,
+                (1, RecvMsg::RpcResponse(_, body)) if body == b"abc"
+            ));
+            assert!(matches!(reader.recv().await?, (0, RecvMsg::Goodbye)));
+            Ok(())
+        }))?;
+        assert!(sent.is_empty());
+        Ok(())
+    }
+}
@@ -1,4 +1,16 @@
-use super::error::{Error, Result};
+use super::{
+    error::{Error, Result},
+    error_message::ErrorMessage,
+    recorder::{Direction, Recorder},
+    streams::RpcSink,
+};
+
+use std::{
+    collections::HashSet,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
 
 use async_std::{io, prelude::*};
 use log::{trace, warn};
@@ -8,7 +20,10 @@ use kuska_handshake::async_std::{BoxStreamRead, BoxStreamWrite};
 
 pub type RequestNo = i32;
 
-const HEADER_SIZE: usize = 9;
+pub(super) const HEADER_SIZE: usize = 9;
+
+/// Default maximum length of the bodies received by `RpcReader::recv`.
+pub const DEFAULT_MAX_BODY_LEN: u32 = 4 * 1024 * 1024;
 
 const RPC_HEADER_STREAM_FLAG: u8 = 1 << 3;
 const RPC_HEADER_END_OR_ERROR_FLAG: u8 = 1 << 2;
@@ -21,7 +36,8 @@ pub enum ArgType {
     Object,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BodyType {
     Binary,
     UTF8,
@@ -58,11 +74,13 @@ pub enum RpcType {
     Async,
     #[serde(rename = "source")]
     Source,
+    #[serde(rename = "sink")]
+    Sink,
     #[serde(rename = "duplex")]
     Duplex,
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Header {
     pub req_no: RequestNo,
     pub is_stream: bool,
@@ -71,13 +89,6 @@ pub struct Header {
     pub body_len: u32,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ErrorMessage<'a> {
-    name: &'a str,
-    stack: &'a str,
-    message: &'a str,
-}
-
 impl Header {
     pub fn from_slice(bytes: &[u8]) -> Result<Header> {
         if bytes.len() < HEADER_SIZE {
@@ -115,6 +126,16 @@ impl Header {
         })
     }
 
+    /// Check if this is the all-zero header the peer sends before closing
+    /// the connection.
+    pub fn is_goodbye(&self) -> bool {
+        self.req_no == 0
+            && self.body_len == 0
+            && !self.is_stream
+            && !self.is_end_or_error
+            && self.body_type == BodyType::Binary
+    }
+
     pub fn to_array(&self) -> [u8; 9] {
         let mut flags: u8 = 0;
         if self.is_end_or_error {
@@ -140,13 +161,106 @@ impl Header {
     }
 }
 
+/// Source of the rpc messages, box-stream or a transport that needs no
+/// encryption.
+enum RpcRead<R> {
+    Box(BoxStreamRead<R>),
+    Plain(R),
+}
+
+impl<R: io::Read + Unpin> io::Read for RpcRead<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            RpcRead::Box(reader) => Pin::new(reader).poll_read(cx, buf),
+            RpcRead::Plain(reader) => Pin::new(reader).poll_read(cx, buf),
+        }
+    }
+}
+
+/// Sink of the rpc messages, box-stream or a transport that needs no
+/// encryption.
+enum RpcWrite<W> {
+    Box(BoxStreamWrite<W>),
+    Plain(W),
+}
+
+impl<W: io::Write + Unpin> io::Write for RpcWrite<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            RpcWrite::Box(writer) => Pin::new(writer).poll_write(cx, buf),
+            RpcWrite::Plain(writer) => Pin::new(writer).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RpcWrite::Box(writer) => Pin::new(writer).poll_flush(cx),
+            RpcWrite::Plain(writer) => Pin::new(writer).poll_flush(cx),
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            RpcWrite::Box(writer) => Pin::new(writer).poll_close(cx),
+            RpcWrite::Plain(writer) => Pin::new(writer).poll_close(cx),
+        }
+    }
+}
+
+/// Stream requests of the peer that are still open, shared by an
+/// `RpcReader` with its `RpcWriter` to forget the ones we end ourselves.
+#[derive(Debug, Clone, Default)]
+pub struct PeerStreams(Arc<Mutex<HashSet<RequestNo>>>);
+
+impl PeerStreams {
+    fn contains(&self, req_no: RequestNo) -> bool {
+        self.0.lock().unwrap().contains(&req_no)
+    }
+
+    fn insert(&self, req_no: RequestNo) {
+        self.0.lock().unwrap().insert(req_no);
+    }
+
+    fn remove(&self, req_no: RequestNo) {
+        self.0.lock().unwrap().remove(&req_no);
+    }
+
+    /// Number of open stream requests of the peer.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 pub struct RpcReader<R: io::Read + Unpin> {
-    box_reader: BoxStreamRead<R>,
+    reader: RpcRead<R>,
+    max_body_len: u32,
+    // unread bytes of the body of the last received header
+    body_remaining: u32,
+    // stream requests of the peer that neither side has ended yet
+    streams: PeerStreams,
+    recorder: Option<Recorder>,
+    // header and body read so far of a message read in pieces, recorded
+    // once the whole body is read
+    pending_record: Option<(Header, Vec<u8>)>,
 }
 
 pub struct RpcWriter<W: io::Write + Unpin> {
-    box_writer: BoxStreamWrite<W>,
+    writer: RpcWrite<W>,
     req_no: RequestNo,
+    recorder: Option<Recorder>,
+    streams: Option<PeerStreams>,
 }
 
 #[derive(Debug)]
@@ -154,29 +268,193 @@ pub enum RecvMsg {
     RpcRequest(Body),
     RpcResponse(BodyType, Vec<u8>),
     OtherRequest(BodyType, Vec<u8>),
-    ErrorResponse(String),
+    ErrorResponse(ErrorMessage),
     CancelStreamResponse(),
+    /// Data sent by the peer on one of its sink or duplex requests.
+    StreamRequestData(BodyType, Vec<u8>),
+    /// The peer ended one of its stream requests.
+    StreamRequestEnd(),
+    /// The peer ended one of its stream requests with an error.
+    StreamRequestError(ErrorMessage),
+    /// The peer is closing the connection.
+    Goodbye,
+}
+
+/// Get the error of a stream end, or `None` if it ended normally.
+fn stream_end_error(body: &[u8]) -> Option<ErrorMessage> {
+    if body == b"true" {
+        return None;
+    }
+    Some(ErrorMessage::from_slice(body))
 }
 
 impl<R: io::Read + Unpin> RpcReader<R> {
     pub fn new(box_reader: BoxStreamRead<R>) -> RpcReader<R> {
-        RpcReader { box_reader }
+        Self::from_transport(RpcRead::Box(box_reader))
     }
 
-    pub async fn recv(&mut self) -> Result<(RequestNo, RecvMsg)> {
-        let mut rpc_header_raw = [0u8; 9];
-        self.box_reader.read_exact(&mut rpc_header_raw[..]).await?;
+    /// Reader over a transport that needs no encryption, e.g. an in-memory
+    /// one in tests.
+    pub fn plain(reader: R) -> RpcReader<R> {
+        Self::from_transport(RpcRead::Plain(reader))
+    }
+
+    fn from_transport(reader: RpcRead<R>) -> RpcReader<R> {
+        RpcReader {
+            reader,
+            max_body_len: DEFAULT_MAX_BODY_LEN,
+            body_remaining: 0,
+            streams: PeerStreams::default(),
+            recorder: None,
+            pending_record: None,
+        }
+    }
+
+    /// Record every message received. Bodies read with `read_body`, or
+    /// skipped, are recorded once read whole, so they are buffered while
+    /// recording, up to `max_body_len`: longer ones are recorded truncated.
+    pub fn record(self, recorder: Recorder) -> Self {
+        Self {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    /// Maximum length of the bodies received by `recv`, larger ones are
+    /// discarded with an `Error::BodyTooLarge`, with the request number of
+    /// the header, i.e. negative on responses.
+    pub fn max_body_len(self, max_body_len: u32) -> Self {
+        Self {
+            max_body_len,
+            ..self
+        }
+    }
+
+    /// Open stream requests of the peer, to be given to the `RpcWriter` of
+    /// the connection with `RpcWriter::peer_streams`.
+    pub fn peer_streams(&self) -> PeerStreams {
+        self.streams.clone()
+    }
+
+    /// Receive the header of the next message, discarding what is left of
+    /// the body of the previous one. Its body is then read with
+    /// `read_body`, which allows reading large bodies like blob chunks in
+    /// pieces, without limiting their length.
+    pub async fn recv_header(&mut self) -> Result<Header> {
+        let (rpc_header, _) = self.read_header().await?;
+        self.start_record(&rpc_header);
+        Ok(rpc_header)
+    }
+
+    /// Read the next piece of the body of the last received header into
+    /// `buf`, returning its length, or 0 once the whole body was read.
+    pub async fn read_body(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = std::cmp::min(buf.len(), self.body_remaining as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+        self.reader.read_exact(&mut buf[..len]).await?;
+        self.body_remaining -= len as u32;
+        if let Some((_, body)) = &mut self.pending_record {
+            let room = (self.max_body_len as usize).saturating_sub(body.len());
+            body.extend_from_slice(&buf[..len.min(room)]);
+        }
+        if self.body_remaining == 0 {
+            self.finish_record();
+        }
+        Ok(len)
+    }
+
+    fn start_record(&mut self, rpc_header: &Header) {
+        if self.recorder.is_some() {
+            self.pending_record = Some((rpc_header.clone(), Vec::new()));
+            if rpc_header.body_len == 0 {
+                self.finish_record();
+            }
+        }
+    }
+
+    fn finish_record(&mut self) {
+        if let (Some(recorder), Some((rpc_header, body))) =
+            (&self.recorder, self.pending_record.take())
+        {
+            recorder.record(Direction::In, &rpc_header, Some(&body));
+        }
+    }
+
+    async fn skip_body(&mut self) -> Result<()> {
+        let mut buf = [0u8; 4096];
+        while self.read_body(&mut buf).await? > 0 {}
+        Ok(())
+    }
+
+    /// Read the next header, returns if it belongs to an open stream request
+    /// of the peer.
+    async fn read_header(&mut self) -> Result<(Header, bool)> {
+        self.skip_body().await?;
+
+        let mut rpc_header_raw = [0u8; HEADER_SIZE];
+        self.reader.read_exact(&mut rpc_header_raw[..]).await?;
         let rpc_header = Header::from_slice(&rpc_header_raw[..])?;
+        self.body_remaining = rpc_header.body_len;
+
+        let mut in_stream = false;
+        if rpc_header.req_no > 0 && rpc_header.is_stream {
+            if rpc_header.is_end_or_error {
+                // also the end of a stream request we already ended
+                in_stream = true;
+                self.streams.remove(rpc_header.req_no);
+            } else if self.streams.contains(rpc_header.req_no) {
+                in_stream = true;
+            } else {
+                // a new stream request
+                self.streams.insert(rpc_header.req_no);
+            }
+        }
+        Ok((rpc_header, in_stream))
+    }
+
+    pub async fn recv(&mut self) -> Result<(RequestNo, RecvMsg)> {
+        let (rpc_header, in_stream) = self.read_header().await?;
+        if rpc_header.is_goodbye() {
+            if let Some(recorder) = &self.recorder {
+                recorder.record(Direction::In, &rpc_header, Some(&[]));
+            }
+            return Ok((0, RecvMsg::Goodbye));
+        }
 
+        if rpc_header.body_len > self.max_body_len {
+            self.start_record(&rpc_header);
+            self.skip_body().await?;
+            return Err(Error::BodyTooLarge {
+                req_no: rpc_header.req_no,
+                len: rpc_header.body_len,
+            });
+        }
         let mut body_raw: Vec<u8> = vec![0; rpc_header.body_len as usize];
-        self.box_reader.read_exact(&mut body_raw[..]).await?;
+        self.reader.read_exact(&mut body_raw[..]).await?;
+        self.body_remaining = 0;
 
         trace!(target: "ssb-rpc", "recv {:?} '{}'",
             rpc_header,
             String::from_utf8_lossy(&body_raw[..])
         );
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::In, &rpc_header, Some(&body_raw));
+        }
 
         if rpc_header.req_no > 0 {
+            if in_stream {
+                let msg = if !rpc_header.is_end_or_error {
+                    RecvMsg::StreamRequestData(rpc_header.body_type, body_raw)
+                } else {
+                    match stream_end_error(&body_raw) {
+                        Some(err) => RecvMsg::StreamRequestError(err),
+                        None => RecvMsg::StreamRequestEnd(),
+                    }
+                };
+                return Ok((rpc_header.req_no, msg));
+            }
             match serde_json::from_slice(&body_raw) {
                 Ok(rpc_body) => Ok((rpc_header.req_no, RecvMsg::RpcRequest(rpc_body))),
                 Err(_) => Ok((
@@ -186,13 +464,14 @@ impl<R: io::Read + Unpin> RpcReader<R> {
             }
         } else if rpc_header.is_end_or_error {
             if rpc_header.is_stream {
-                Ok((-rpc_header.req_no, RecvMsg::CancelStreamResponse()))
+                let msg = match stream_end_error(&body_raw) {
+                    Some(err) => RecvMsg::ErrorResponse(err),
+                    None => RecvMsg::CancelStreamResponse(),
+                };
+                Ok((-rpc_header.req_no, msg))
             } else {
-                let err: ErrorMessage = serde_json::from_slice(&body_raw)?;
-                Ok((
-                    -rpc_header.req_no,
-                    RecvMsg::ErrorResponse(err.message.to_string()),
-                ))
+                let err = ErrorMessage::from_slice(&body_raw);
+                Ok((-rpc_header.req_no, RecvMsg::ErrorResponse(err)))
             }
         } else {
             Ok((
@@ -202,10 +481,25 @@ impl<R: io::Read + Unpin> RpcReader<R> {
         }
     }
 
+    /// Stream of the received messages, until a goodbye or an error.
+    /// Messages with too large bodies are skipped.
     pub fn into_stream(mut self) -> impl Stream<Item = (RequestNo, RecvMsg)> {
         stream! {
-            while let Ok(v) = self.recv().await {
-                yield v
+            loop {
+                let v = match self.recv().await {
+                    Ok(v) => v,
+                    Err(Error::BodyTooLarge { req_no, len }) => {
+                        warn!(target: "ssb-rpc",
+                            "skipped body of {} bytes, on request {}", len, req_no);
+                        continue;
+                    }
+                    Err(_) => break,
+                };
+                let goodbye = matches!(v.1, RecvMsg::Goodbye);
+                yield v;
+                if goodbye {
+                    break;
+                }
             }
         }
     }
@@ -214,8 +508,38 @@ impl<R: io::Read + Unpin> RpcReader<R> {
 impl<W: io::Write + Unpin> RpcWriter<W> {
     pub fn new(box_writer: BoxStreamWrite<W>) -> RpcWriter<W> {
         RpcWriter {
-            box_writer,
+            writer: RpcWrite::Box(box_writer),
+            req_no: 0,
+            recorder: None,
+            streams: None,
+        }
+    }
+
+    /// Writer over a transport that needs no encryption, e.g. an in-memory
+    /// one in tests.
+    pub fn plain(writer: W) -> RpcWriter<W> {
+        RpcWriter {
+            writer: RpcWrite::Plain(writer),
             req_no: 0,
+            recorder: None,
+            streams: None,
+        }
+    }
+
+    /// Record every message sent.
+    pub fn record(self, recorder: Recorder) -> Self {
+        Self {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    /// Forget the stream requests of the peer in `RpcReader::peer_streams`
+    /// once we end them, so they are not kept until the peer ends them too.
+    pub fn peer_streams(self, streams: PeerStreams) -> Self {
+        Self {
+            streams: Some(streams),
+            ..self
         }
     }
 
@@ -250,19 +574,13 @@ impl<W: io::Write + Unpin> RpcWriter<W> {
 
         let rpc_header = Header {
             req_no: self.req_no,
-            is_stream: rpc_type == RpcType::Source || rpc_type == RpcType::Duplex,
+            is_stream: rpc_type != RpcType::Async,
             is_end_or_error: false,
             body_type: BodyType::JSON,
             body_len: body_str.as_bytes().len() as u32,
         };
 
-        trace!(target: "ssb-rpc", "send {:?} '{}'", rpc_header, body_str);
-
-        self.box_writer
-            .write_all(&rpc_header.to_array()[..])
-            .await?;
-        self.box_writer.write_all(body_str.as_bytes()).await?;
-        self.box_writer.flush().await?;
+        self.send_frame(&rpc_header, body_str.as_bytes()).await?;
 
         Ok(self.req_no)
     }
@@ -276,23 +594,13 @@ impl<W: io::Write + Unpin> RpcWriter<W> {
     ) -> Result<()> {
         let rpc_header = Header {
             req_no: -req_no,
-            is_stream: rpc_type == RpcType::Source,
+            is_stream: rpc_type != RpcType::Async,
             is_end_or_error: false,
             body_type,
             body_len: body.len() as u32,
         };
 
-        trace!(target: "ssb-rpc",
-            "send {:?} '{}'",
-            rpc_header,
-            String::from_utf8_lossy(body)
-        );
-
-        self.box_writer
-            .write_all(&rpc_header.to_array()[..])
-            .await?;
-        self.box_writer.write_all(body).await?;
-        self.box_writer.flush().await?;
+        self.send_frame(&rpc_header, body).await?;
 
         Ok(())
     }
@@ -303,11 +611,19 @@ impl<W: io::Write + Unpin> RpcWriter<W> {
         rpc_type: RpcType,
         message: &str,
     ) -> Result<()> {
-        let body_bytes = serde_json::to_string(&ErrorMessage {
-            name: "Error",
-            stack: "",
-            message,
-        })?;
+        self.send_error_message(req_no, rpc_type, &ErrorMessage::new(message))
+            .await
+    }
+
+    /// Send an error response keeping its name, e.g. to answer with a
+    /// `NotFoundError`.
+    pub async fn send_error_message(
+        &mut self,
+        req_no: RequestNo,
+        rpc_type: RpcType,
+        err: &ErrorMessage,
+    ) -> Result<()> {
+        let body_bytes = serde_json::to_string(err)?;
 
         let is_stream = !matches!(rpc_type, RpcType::Async);
 
@@ -319,13 +635,7 @@ impl<W: io::Write + Unpin> RpcWriter<W> {
             body_len: body_bytes.as_bytes().len() as u32,
         };
 
-        trace!(target: "ssb-rpc", "send {:?} '{}'", rpc_header, body_bytes);
-
-        self.box_writer
-            .write_all(&rpc_header.to_array()[..])
-            .await?;
-        self.box_writer.write_all(body_bytes.as_bytes()).await?;
-        self.box_writer.flush().await?;
+        self.send_frame(&rpc_header, body_bytes.as_bytes()).await?;
 
         Ok(())
     }
@@ -341,29 +651,270 @@ impl<W: io::Write + Unpin> RpcWriter<W> {
             body_len: body_bytes.len() as u32,
         };
 
+        self.send_frame(&rpc_header, &body_bytes[..]).await?;
+        Ok(())
+    }
+
+    /// Writing half of our sink or duplex request `req_no`.
+    pub fn request_sink(&mut self, req_no: RequestNo) -> RpcSink<'_, W> {
+        RpcSink::new(self, req_no)
+    }
+
+    /// Writing half of the responses to the duplex or source request
+    /// `req_no` of the peer.
+    pub fn response_sink(&mut self, req_no: RequestNo) -> RpcSink<'_, W> {
+        RpcSink::new(self, -req_no)
+    }
+
+    /// Send a message of a stream, `req_no` being the request number on the
+    /// wire, i.e. negative for responses.
+    pub(super) async fn send_stream_message(
+        &mut self,
+        req_no: RequestNo,
+        is_end_or_error: bool,
+        body_type: BodyType,
+        body: &[u8],
+    ) -> Result<()> {
+        let rpc_header = Header {
+            req_no,
+            is_stream: true,
+            is_end_or_error,
+            body_type,
+            body_len: body.len() as u32,
+        };
+
+        self.send_frame(&rpc_header, body).await?;
+        Ok(())
+    }
+
+    /// Send the error ending a stream, `req_no` being the request number on
+    /// the wire.
+    pub(super) async fn send_stream_error(
+        &mut self,
+        req_no: RequestNo,
+        err: &ErrorMessage,
+    ) -> Result<()> {
+        let body = serde_json::to_vec(err)?;
+        self.send_stream_message(req_no, true, BodyType::JSON, &body)
+            .await
+    }
+
+    async fn send_frame(&mut self, rpc_header: &Header, body: &[u8]) -> Result<()> {
         trace!(target: "ssb-rpc",
             "send {:?} '{}'",
             rpc_header,
-            String::from_utf8_lossy(body_bytes)
+            String::from_utf8_lossy(body)
         );
 
-        self.box_writer
-            .write_all(&rpc_header.to_array()[..])
-            .await?;
-        self.box_writer.write_all(&body_bytes[..]).await?;
-        self.box_writer.flush().await?;
+        self.writer.write_all(&rpc_header.to_array()[..]).await?;
+        self.writer.write_all(body).await?;
+        self.writer.flush().await?;
+
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Direction::Out, rpc_header, Some(body));
+        }
+        if let Some(streams) = &self.streams {
+            if rpc_header.req_no < 0 && rpc_header.is_stream && rpc_header.is_end_or_error {
+                streams.remove(-rpc_header.req_no);
+            }
+        }
         Ok(())
     }
 
     pub async fn close(&mut self) -> Result<()> {
-        self.box_writer.goodbye().await?;
+        match &mut self.writer {
+            RpcWrite::Box(writer) => writer.goodbye().await?,
+            RpcWrite::Plain(writer) => {
+                writer.write_all(&[0u8; HEADER_SIZE]).await?;
+                futures::AsyncWriteExt::close(writer).await?;
+            }
+        }
+        if let Some(recorder) = &self.recorder {
+            let goodbye = Header::from_slice(&[0u8; HEADER_SIZE])?;
+            recorder.record(Direction::Out, &goodbye, Some(&[]));
+        }
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{BodyType, Header};
+    use super::{ArgType, BodyType, Header, RecvMsg, RpcType};
+    use crate::rpc::{Error, ErrorMessage, MemoryTransport, Result};
+    use async_std::task::block_on;
+
+    #[test]
+    fn test_duplex_call() -> Result<()> {
+        let ((mut client_reader, mut client_writer), (mut server_reader, mut server_writer)) =
+            MemoryTransport::new().connect();
+        block_on(async {
+            let args: [&str; 0] = [];
+            let req_no = client_writer
+                .send_request(
+                    &["echo"],
+                    RpcType::Duplex,
+                    ArgType::Array,
+                    &args,
+                    &None::<()>,
+                )
+                .await?;
+            let mut sink = client_writer.request_sink(req_no);
+            sink.send_json(&1).await?;
+            sink.end().await?;
+            assert!(matches!(sink.send_json(&2).await, Err(Error::StreamEnded)));
+
+            assert!(matches!(
+                server_reader.recv().await?,
+                (1, RecvMsg::RpcRequest(body)) if body.rpc_type == RpcType::Duplex
+            ));
+            assert!(matches!(
+                server_reader.recv().await?,
+                (1, RecvMsg::StreamRequestData(BodyType::JSON, body)) if body == b"1"
+            ));
+            assert!(matches!(
+                server_reader.recv().await?,
+                (1, RecvMsg::StreamRequestEnd())
+            ));
+
+            let mut sink = server_writer.response_sink(req_no);
+            sink.send(BodyType::Binary, &[1]).await?;
+            sink.error(&ErrorMessage::new("done")).await?;
+            assert!(matches!(
+                client_reader.recv().await?,
+                (1, RecvMsg::RpcResponse(BodyType::Binary, body)) if body == [1]
+            ));
+            assert!(matches!(
+                client_reader.recv().await?,
+                (1, RecvMsg::ErrorResponse(err)) if err.message == "done"
+            ));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_end_peer_stream() -> Result<()> {
+        let ((mut client_reader, mut client_writer), (mut server_reader, mut server_writer)) =
+            MemoryTransport::new().connect();
+        block_on(async {
+            let args: [&str; 0] = [];
+            let req_no = client_writer
+                .send_request(&["sink"], RpcType::Sink, ArgType::Array, &args, &None::<()>)
+                .await?;
+            assert!(matches!(
+                server_reader.recv().await?,
+                (1, RecvMsg::RpcRequest(_))
+            ));
+            assert_eq!(server_reader.peer_streams().len(), 1);
+
+            // ended by us, forgotten without waiting for the end of the peer
+            server_writer.send_stream_eof(req_no).await?;
+            assert!(server_reader.peer_streams().is_empty());
+            assert!(matches!(
+                client_reader.recv().await?,
+                (1, RecvMsg::CancelStreamResponse())
+            ));
+
+            // whose late end is still taken as the end of the stream
+            client_writer.request_sink(req_no).end().await?;
+            assert!(matches!(
+                server_reader.recv().await?,
+                (1, RecvMsg::StreamRequestEnd())
+            ));
+            assert!(server_reader.peer_streams().is_empty());
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_body_limits() -> Result<()> {
+        let ((_, mut writer), (reader, _)) = MemoryTransport::new().connect();
+        let mut reader = reader.max_body_len(4);
+        block_on(async {
+            writer
+                .send_response(1, RpcType::Async, BodyType::Binary, b"too long")
+                .await?;
+            writer
+                .send_response(2, RpcType::Async, BodyType::Binary, b"ok")
+                .await?;
+            writer
+                .send_response(3, RpcType::Source, BodyType::Binary, b"in pieces")
+                .await?;
+
+            assert!(matches!(
+                reader.recv().await,
+                Err(Error::BodyTooLarge { req_no: -1, len: 8 })
+            ));
+            assert!(matches!(
+                reader.recv().await?,
+                (2, RecvMsg::RpcResponse(_, body)) if body == b"ok"
+            ));
+
+            let header = reader.recv_header().await?;
+            assert_eq!(header.req_no, -3);
+            let mut buf = [0u8; 4];
+            let mut body = Vec::new();
+            loop {
+                let len = reader.read_body(&mut buf).await?;
+                if len == 0 {
+                    break;
+                }
+                body.extend_from_slice(&buf[..len]);
+            }
+            assert_eq!(body, b"in pieces");
+
+            // requests of the peer keep their positive number
+            let req_no = writer
+                .send_request(
+                    &["whoami"],
+                    RpcType::Async,
+                    ArgType::Array,
+                    &(),
+                    &None::<()>,
+                )
+                .await?;
+            assert!(matches!(
+                reader.recv().await,
+                Err(Error::BodyTooLarge { req_no: n, .. }) if n == req_no && n > 0
+            ));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_body_limits_stream() -> Result<()> {
+        let ((_, mut writer), (reader, _)) = MemoryTransport::new().connect();
+        let stream = reader.max_body_len(4).into_stream();
+        block_on(async {
+            writer
+                .send_response(1, RpcType::Async, BodyType::Binary, b"too long")
+                .await?;
+            writer
+                .send_response(2, RpcType::Async, BodyType::Binary, b"ok")
+                .await?;
+            writer.close().await?;
+
+            let msgs: Vec<_> = futures::StreamExt::collect(stream).await;
+            assert!(matches!(
+                &msgs[..],
+                [(2, RecvMsg::RpcResponse(_, body)), (0, RecvMsg::Goodbye)] if body == b"ok"
+            ));
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_header_goodbye() {
+        let h = Header::from_slice(&[0u8; 9]).unwrap();
+        assert!(h.is_goodbye());
+        let h = Header {
+            req_no: 0,
+            is_stream: false,
+            is_end_or_error: false,
+            body_type: BodyType::JSON,
+            body_len: 0,
+        };
+        assert!(!h.is_goodbye());
+    }
 
     #[test]
     fn test_header_encoding_1() {
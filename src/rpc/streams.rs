@@ -0,0 +1,631 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_std::io;
+use futures::{
+    channel::{mpsc, oneshot},
+    task::AtomicWaker,
+    Future, Stream,
+};
+
+use super::{
+    error::{Error, Result},
+    error_message::ErrorMessage,
+    stream::{BodyType, RecvMsg, RequestNo, RpcWriter},
+};
+
+/// Writing half of a sink or duplex call, see `RpcWriter::request_sink` and
+/// `RpcWriter::response_sink`.
+///
+/// The stream must be closed with `end` or `error`, the peer is not told
+/// about it when the sink is just dropped.
+pub struct RpcSink<'a, W: io::Write + Unpin> {
+    writer: &'a mut RpcWriter<W>,
+    // request number on the wire, negative on the callee side
+    req_no: RequestNo,
+    ended: bool,
+}
+
+impl<'a, W: io::Write + Unpin> RpcSink<'a, W> {
+    pub(super) fn new(writer: &'a mut RpcWriter<W>, req_no: RequestNo) -> Self {
+        RpcSink {
+            writer,
+            req_no,
+            ended: false,
+        }
+    }
+
+    /// The request number of the call.
+    pub fn req_no(&self) -> RequestNo {
+        self.req_no.abs()
+    }
+
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    pub async fn send(&mut self, body_type: BodyType, body: &[u8]) -> Result<()> {
+        if self.ended {
+            return Err(Error::StreamEnded);
+        }
+        self.writer
+            .send_stream_message(self.req_no, false, body_type, body)
+            .await
+    }
+
+    pub async fn send_json<T: serde::Serialize>(&mut self, value: &T) -> Result<()> {
+        let body = serde_json::to_vec(value)?;
+        self.send(BodyType::JSON, &body).await
+    }
+
+    /// End the stream, does nothing if it was already ended.
+    pub async fn end(&mut self) -> Result<()> {
+        if self.ended {
+            return Ok(());
+        }
+        self.ended = true;
+        self.writer
+            .send_stream_message(self.req_no, true, BodyType::JSON, b"true")
+            .await
+    }
+
+    /// End the stream with an error.
+    pub async fn error(&mut self, err: &ErrorMessage) -> Result<()> {
+        if self.ended {
+            return Err(Error::StreamEnded);
+        }
+        self.ended = true;
+        self.writer.send_stream_error(self.req_no, err).await
+    }
+}
+
+type StreamItem = Result<(BodyType, Vec<u8>)>;
+
+/// Default number of messages queued for each stream.
+pub const DEFAULT_STREAM_CAPACITY: usize = 64;
+
+#[derive(Debug)]
+struct PauseState {
+    paused: AtomicBool,
+    waker: AtomicWaker,
+    queued: AtomicUsize,
+    capacity: usize,
+}
+
+/// Pauses and resumes the reading half of a stream, possibly from another
+/// task.
+///
+/// Pausing does not stop the peer, muxrpc has no flow control: the
+/// messages are buffered meanwhile, up to the capacity of the stream. If
+/// it overflows the stream fails with `Error::StreamOverflow`, so it has to
+/// be resumed before `queued` reaches `capacity`. Other streams are not
+/// affected.
+#[derive(Debug, Clone)]
+pub struct PauseHandle(Arc<PauseState>);
+
+impl PauseHandle {
+    fn new(capacity: usize) -> Self {
+        PauseHandle(Arc::new(PauseState {
+            paused: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+            queued: AtomicUsize::new(0),
+            capacity,
+        }))
+    }
+
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::SeqCst);
+        self.0.waker.wake();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// Number of messages buffered, not read yet.
+    pub fn queued(&self) -> usize {
+        self.0.queued.load(Ordering::SeqCst)
+    }
+
+    /// Number of messages that can be buffered before the stream overflows.
+    pub fn capacity(&self) -> usize {
+        self.0.capacity
+    }
+
+    // queue `item` on `tx`, counting it
+    fn queue(&self, tx: &mut mpsc::Sender<StreamItem>, item: StreamItem) -> bool {
+        self.0.queued.fetch_add(1, Ordering::SeqCst);
+        let queued = tx.try_send(item).is_ok();
+        if !queued {
+            self.0.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        queued
+    }
+}
+
+/// Reading half of a source, sink or duplex call, fed by a `StreamRouter`.
+///
+/// It finishes when the peer ends the stream, after yielding an
+/// `Error::Remote` if it ended with an error.
+#[derive(Debug)]
+pub struct RpcStream {
+    req_no: RequestNo,
+    rx: mpsc::Receiver<StreamItem>,
+    pause: PauseHandle,
+}
+
+impl RpcStream {
+    pub fn req_no(&self) -> RequestNo {
+        self.req_no
+    }
+
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    pub fn pause(&self) {
+        self.pause.pause()
+    }
+
+    pub fn resume(&self) {
+        self.pause.resume()
+    }
+}
+
+impl Stream for RpcStream {
+    type Item = StreamItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.pause.is_paused() {
+            self.pause.0.waker.register(cx.waker());
+            // resumed while registering
+            if self.pause.is_paused() {
+                return Poll::Pending;
+            }
+        }
+        let item = Pin::new(&mut self.rx).poll_next(cx);
+        if let Poll::Ready(Some(_)) = item {
+            self.pause.0.queued.fetch_sub(1, Ordering::SeqCst);
+        }
+        item
+    }
+}
+
+/// Response to one of our async requests, see
+/// `StreamRouter::async_response`.
+#[derive(Debug)]
+pub struct AsyncResponse {
+    req_no: RequestNo,
+    rx: oneshot::Receiver<StreamItem>,
+}
+
+impl AsyncResponse {
+    pub fn req_no(&self) -> RequestNo {
+        self.req_no
+    }
+
+    /// Wait for the response for at most `timeout`, failing with
+    /// `Error::Timeout` otherwise.
+    pub async fn timeout(self, timeout: Duration) -> StreamItem {
+        let req_no = self.req_no;
+        async_std::future::timeout(timeout, self)
+            .await
+            .unwrap_or(Err(Error::Timeout(req_no)))
+    }
+}
+
+impl Future for AsyncResponse {
+    type Output = StreamItem;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(item)) => Poll::Ready(item),
+            // the router was dropped
+            Poll::Ready(Err(_)) => Poll::Ready(Err(Error::Closed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Route {
+    Open {
+        tx: mpsc::Sender<StreamItem>,
+        // each sender has a slot of its own, so the final error always fits
+        last: mpsc::Sender<StreamItem>,
+        pause: PauseHandle,
+    },
+    /// Full queue, to be aborted with `abort_dropped`. Messages are
+    /// discarded meanwhile.
+    Overflowed,
+    /// Aborted or dropped reading half, messages are discarded until the
+    /// peer ends the stream.
+    Aborted,
+}
+
+type Routes = HashMap<RequestNo, Route>;
+
+/// Dispatches the messages received by an `RpcReader` to the reading
+/// halves of the open streams.
+///
+/// Each stream buffers a bounded number of messages, see `PauseHandle`.
+/// Routing never waits: a stream whose buffer overflows fails with
+/// `Error::StreamOverflow`, and has to be aborted with `abort_dropped`.
+#[derive(Debug)]
+pub struct StreamRouter {
+    capacity: usize,
+    // our requests, receiving responses
+    responses: Routes,
+    // requests of the peer, receiving its sink or duplex data
+    requests: Routes,
+    // our async requests
+    asyncs: HashMap<RequestNo, oneshot::Sender<StreamItem>>,
+}
+
+impl Default for StreamRouter {
+    fn default() -> Self {
+        StreamRouter {
+            capacity: DEFAULT_STREAM_CAPACITY,
+            responses: HashMap::new(),
+            requests: HashMap::new(),
+            asyncs: HashMap::new(),
+        }
+    }
+}
+
+impl StreamRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of messages buffered for each stream, at least one.
+    pub fn capacity(self, capacity: usize) -> Self {
+        Self { capacity, ..self }
+    }
+
+    /// Reading half of the responses to our request `req_no`.
+    pub fn response_stream(&mut self, req_no: RequestNo) -> RpcStream {
+        let capacity = self.capacity;
+        Self::open(&mut self.responses, req_no, capacity)
+    }
+
+    /// Reading half of the data sent by the peer on its sink or duplex
+    /// request `req_no`.
+    pub fn request_stream(&mut self, req_no: RequestNo) -> RpcStream {
+        let capacity = self.capacity;
+        Self::open(&mut self.requests, req_no, capacity)
+    }
+
+    /// Response to our async request `req_no`. Once it is not awaited
+    /// anymore, e.g. after its timeout, the request is forgotten and a late
+    /// response is returned back by `route`.
+    pub fn async_response(&mut self, req_no: RequestNo) -> AsyncResponse {
+        self.prune_asyncs();
+        let (tx, rx) = oneshot::channel();
+        self.asyncs.insert(req_no, tx);
+        AsyncResponse { req_no, rx }
+    }
+
+    fn open(streams: &mut Routes, req_no: RequestNo, capacity: usize) -> RpcStream {
+        // the sender adds one slot to the channel buffer
+        let (tx, rx) = mpsc::channel(capacity.max(1) - 1);
+        let last = tx.clone();
+        let pause = PauseHandle::new(capacity.max(1));
+        streams.insert(
+            req_no,
+            Route::Open {
+                tx,
+                last,
+                pause: pause.clone(),
+            },
+        );
+        RpcStream { req_no, rx, pause }
+    }
+
+    /// Number of open streams.
+    pub fn len(&self) -> usize {
+        let open = |streams: &Routes| {
+            streams
+                .values()
+                .filter(|route| matches!(route, Route::Open { .. }))
+                .count()
+        };
+        open(&self.responses) + open(&self.requests)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Abort our request `req_no`, closing its reading half and telling the
+    /// peer to stop sending.
+    pub async fn abort_response_stream<W: io::Write + Unpin>(
+        &mut self,
+        writer: &mut RpcWriter<W>,
+        req_no: RequestNo,
+    ) -> Result<()> {
+        self.responses.insert(req_no, Route::Aborted);
+        // our end goes on the request number itself
+        writer.send_stream_eof(-req_no).await
+    }
+
+    /// Abort the sink or duplex request `req_no` of the peer, closing its
+    /// reading half and telling the peer to stop sending.
+    pub async fn abort_request_stream<W: io::Write + Unpin>(
+        &mut self,
+        writer: &mut RpcWriter<W>,
+        req_no: RequestNo,
+    ) -> Result<()> {
+        self.requests.insert(req_no, Route::Aborted);
+        writer.send_stream_eof(req_no).await
+    }
+
+    /// Abort the streams whose reading half was dropped, or whose queue
+    /// overflowed.
+    pub async fn abort_dropped<W: io::Write + Unpin>(
+        &mut self,
+        writer: &mut RpcWriter<W>,
+    ) -> Result<()> {
+        let dropped = |streams: &Routes| -> Vec<RequestNo> {
+            streams
+                .iter()
+                .filter(|(_, route)| match route {
+                    Route::Open { tx, .. } => tx.is_closed(),
+                    Route::Overflowed => true,
+                    Route::Aborted => false,
+                })
+                .map(|(req_no, _)| *req_no)
+                .collect()
+        };
+        for req_no in dropped(&self.responses) {
+            self.abort_response_stream(writer, req_no).await?;
+        }
+        for req_no in dropped(&self.requests) {
+            self.abort_request_stream(writer, req_no).await?;
+        }
+        Ok(())
+    }
+
+    /// Route a received message to the stream waiting for it. Returns the
+    /// message back when there is no stream, e.g. for new requests.
+    pub fn route(&mut self, req_no: RequestNo, msg: RecvMsg) -> Option<(RequestNo, RecvMsg)> {
+        self.prune_asyncs();
+        let msg = match msg {
+            RecvMsg::RpcResponse(body_type, body) if self.asyncs.contains_key(&req_no) => {
+                self.respond(req_no, Ok((body_type, body)));
+                return None;
+            }
+            RecvMsg::ErrorResponse(err) if self.asyncs.contains_key(&req_no) => {
+                self.respond(req_no, Err(Error::Remote(err)));
+                return None;
+            }
+            msg => msg,
+        };
+        let streams = match msg {
+            RecvMsg::RpcResponse(..)
+            | RecvMsg::ErrorResponse(_)
+            | RecvMsg::CancelStreamResponse() => &mut self.responses,
+            RecvMsg::StreamRequestData(..)
+            | RecvMsg::StreamRequestError(_)
+            | RecvMsg::StreamRequestEnd() => &mut self.requests,
+            _ => return Some((req_no, msg)),
+        };
+        let (tx, last, pause) = match streams.get_mut(&req_no) {
+            Some(Route::Open { tx, last, pause }) => (tx, last, pause),
+            Some(Route::Overflowed) | Some(Route::Aborted) => {
+                if !matches!(
+                    msg,
+                    RecvMsg::RpcResponse(..) | RecvMsg::StreamRequestData(..)
+                ) {
+                    streams.remove(&req_no);
+                }
+                return None;
+            }
+            None => return Some((req_no, msg)),
+        };
+        match msg {
+            RecvMsg::RpcResponse(body_type, body) | RecvMsg::StreamRequestData(body_type, body) => {
+                // once the reading half is dropped, messages are discarded
+                // until aborted with `abort_dropped`
+                if !pause.queue(tx, Ok((body_type, body))) && !tx.is_closed() {
+                    pause.queue(last, Err(Error::StreamOverflow(req_no)));
+                    streams.insert(req_no, Route::Overflowed);
+                }
+            }
+            RecvMsg::ErrorResponse(err) | RecvMsg::StreamRequestError(err) => {
+                pause.queue(last, Err(Error::Remote(err)));
+                streams.remove(&req_no);
+            }
+            _ => {
+                streams.remove(&req_no);
+            }
+        }
+        None
+    }
+
+    // forget the async requests not awaited anymore
+    fn prune_asyncs(&mut self) {
+        self.asyncs.retain(|_, tx| !tx.is_canceled());
+    }
+
+    fn respond(&mut self, req_no: RequestNo, item: StreamItem) {
+        if let Some(tx) = self.asyncs.remove(&req_no) {
+            // fails if it is not awaited anymore
+            let _ = tx.send(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rpc::ErrorKind;
+    use crate::rpc::RpcReader;
+    use async_std::task::block_on;
+    use futures::{FutureExt, StreamExt};
+
+    fn data(body: &str) -> RecvMsg {
+        RecvMsg::RpcResponse(BodyType::JSON, body.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_route_streams() {
+        let mut router = StreamRouter::new();
+        let responses = router.response_stream(1);
+        let requests = router.request_stream(1);
+        let failing = router.request_stream(2);
+        assert_eq!(router.len(), 3);
+
+        assert!(router.route(1, data("1")).is_none());
+        assert!(router
+            .route(1, RecvMsg::StreamRequestData(BodyType::Binary, vec![1, 2]))
+            .is_none());
+        assert!(router.route(1, data("2")).is_none());
+        assert!(router.route(1, RecvMsg::CancelStreamResponse()).is_none());
+        assert!(router.route(1, RecvMsg::StreamRequestEnd()).is_none());
+        assert!(router
+            .route(2, RecvMsg::StreamRequestError(ErrorMessage::new("boom")))
+            .is_none());
+        assert!(router.is_empty());
+
+        // no stream left for them
+        assert!(matches!(
+            router.route(1, data("3")),
+            Some((1, RecvMsg::RpcResponse(..)))
+        ));
+        assert!(matches!(
+            router.route(3, RecvMsg::OtherRequest(BodyType::UTF8, vec![])),
+            Some((3, RecvMsg::OtherRequest(..)))
+        ));
+
+        let responses: Vec<_> = block_on(responses.collect::<Vec<_>>())
+            .into_iter()
+            .map(|item| item.unwrap().1)
+            .collect();
+        assert_eq!(responses, vec![b"1".to_vec(), b"2".to_vec()]);
+        let requests: Vec<_> = block_on(requests.collect());
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(&requests[0], Ok((BodyType::Binary, body)) if body == &[1, 2]));
+        let failing: Vec<_> = block_on(failing.collect());
+        assert!(matches!(&failing[..], [Err(Error::Remote(err))] if err.message == "boom"));
+    }
+
+    #[test]
+    fn test_overflow_and_pause() -> Result<()> {
+        let mut router = StreamRouter::new().capacity(2);
+        let mut paused = router.response_stream(1);
+        let mut other = router.response_stream(3);
+        let response = router.async_response(5);
+
+        paused.pause();
+        for body in ["1", "2", "3", "4"] {
+            assert!(router.route(1, data(body)).is_none());
+        }
+        // the other streams are still delivered
+        assert!(router.route(3, data("3")).is_none());
+        assert!(router.route(5, data("5")).is_none());
+        assert!(matches!(block_on(other.next()), Some(Ok((_, body))) if body == b"3"));
+        assert!(matches!(block_on(response), Ok((_, body)) if body == b"5"));
+
+        assert!(paused.next().now_or_never().is_none());
+        paused.resume();
+        let items: Vec<_> = block_on(paused.collect());
+        assert_eq!(items.len(), 3);
+        assert!(matches!(&items[..2], [Ok((_, one)), Ok((_, two))] if one == b"1" && two == b"2"));
+        assert!(matches!(items[2], Err(Error::StreamOverflow(1))));
+
+        // only the overflowed stream is aborted
+        let (writer, reader) = super::super::memory::pipe();
+        let (mut writer, mut reader) = (RpcWriter::plain(writer), RpcReader::plain(reader));
+        block_on(router.abort_dropped(&mut writer))?;
+        let header = block_on(reader.recv_header())?;
+        assert_eq!(header.req_no, 1);
+        assert!(header.is_end_or_error);
+        assert_eq!(router.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resume_before_overflow() {
+        let mut router = StreamRouter::new().capacity(2);
+        let mut stream = router.response_stream(1);
+        let pause = stream.pause_handle();
+        assert_eq!(pause.capacity(), 2);
+
+        pause.pause();
+        let mut bodies = Vec::new();
+        for body in ["1", "2", "3", "4", "5"] {
+            if pause.queued() == pause.capacity() {
+                pause.resume();
+                while pause.queued() > 0 {
+                    bodies.push(block_on(stream.next()).unwrap().unwrap().1);
+                }
+                pause.pause();
+            }
+            assert!(router.route(1, data(body)).is_none());
+        }
+        assert_eq!(pause.queued(), 1);
+        assert!(router.route(1, RecvMsg::CancelStreamResponse()).is_none());
+
+        pause.resume();
+        for item in block_on(stream.collect::<Vec<_>>()) {
+            bodies.push(item.unwrap().1);
+        }
+        assert_eq!(bodies, vec![b"1", b"2", b"3", b"4", b"5"]);
+        assert_eq!(pause.queued(), 0);
+    }
+
+    #[test]
+    fn test_dropped_stream() {
+        let mut router = StreamRouter::new();
+        let stream = router.response_stream(1);
+        drop(stream);
+        // discarded until aborted
+        assert!(router.route(1, data("1")).is_none());
+        assert!(router.route(1, RecvMsg::CancelStreamResponse()).is_none());
+        assert!(router.is_empty());
+    }
+
+    #[test]
+    fn test_async_response() {
+        let mut router = StreamRouter::new();
+        let ok = router.async_response(1);
+        let failing = router.async_response(2);
+        let late = router.async_response(3);
+
+        block_on(async {
+            assert!(router.route(1, data("1")).is_none());
+            assert!(router
+                .route(2, RecvMsg::ErrorResponse(ErrorMessage::not_found("boom")))
+                .is_none());
+            // only one response for async requests
+            assert!(router.route(1, data("2")).is_some());
+
+            assert!(matches!(ok.await, Ok((BodyType::JSON, body)) if body == b"1"));
+            assert!(matches!(
+                failing.await,
+                Err(Error::Remote(err)) if err.kind() == ErrorKind::NotFound
+            ));
+            assert!(matches!(
+                late.timeout(Duration::from_millis(10)).await,
+                Err(Error::Timeout(3))
+            ));
+            // forgotten after the timeout
+            let _pending = router.async_response(4);
+            assert_eq!(router.asyncs.len(), 1);
+            assert!(router.route(3, data("3")).is_some());
+        });
+    }
+}